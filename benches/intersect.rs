@@ -0,0 +1,104 @@
+// Compares tracing rays through spheres one object at a time against the sphere group, for single
+// rays and packets of four. Run with `cargo bench`, and again with `cargo bench --features simd` to
+// see what the SIMD lanes gain.
+use std::hint::black_box;
+use std::rc::Rc;
+use std::time::Instant;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use raytracing::hittable::{HitRecord, Hittable, HittableList};
+use raytracing::interval::Interval;
+use raytracing::material::Lambertian;
+use raytracing::ray::Ray;
+use raytracing::sphere::Sphere;
+use raytracing::sphere_group::{RayPacket, SphereGroup};
+use raytracing::vec3::Vec3;
+use raytracing::rtweekend::Real;
+
+const RAYS: usize = 200_000;
+
+// The small spheres of the cover scene.
+fn spheres(rng: &mut StdRng) -> Vec<(Vec3, Real)> {
+    let mut spheres = Vec::new();
+    for a in -11..=11 {
+        for b in -11..=11 {
+            let center = Vec3::new(a as Real + 0.9 * rng.gen::<Real>(), 0.2, b as Real + 0.9 * rng.gen::<Real>());
+            spheres.push((center, 0.2));
+        }
+    }
+    spheres
+}
+
+// Rays from the cover camera towards random points on the ground.
+fn rays(rng: &mut StdRng) -> Vec<Ray> {
+    let origin = Vec3::new(13.0, 2.0, 3.0);
+    (0..RAYS)
+        .map(|_| {
+            let target = Vec3::new(rng.gen_range(-11.0..11.0), 0.0, rng.gen_range(-11.0..11.0));
+            Ray::new(origin, target - origin)
+        })
+        .collect()
+}
+
+// Runs trace over all rays, returning nanoseconds per ray and a checksum of the hit distances.
+fn measure(name: &str, rays: &[Ray], trace: impl Fn(&[Ray]) -> Real) -> Real {
+    let start = Instant::now();
+    let checksum = trace(black_box(rays));
+    let nanoseconds = start.elapsed().as_nanos() as Real / rays.len() as Real;
+    println!("{:<28} {:>8.1} ns/ray   checksum {:.6}", name, nanoseconds, checksum);
+    nanoseconds
+}
+
+fn main() {
+    let mut rng = StdRng::seed_from_u64(1);
+    let material = Rc::new(Lambertian::new(Vec3::new(0.5, 0.5, 0.5)));
+    let spheres = spheres(&mut rng);
+    let rays = rays(&mut rng);
+    let ray_t = Interval::new(0.0, Real::INFINITY);
+
+    let mut list = HittableList::new(Box::new(Sphere::new(spheres[0].0, spheres[0].1, material.clone())));
+    let mut group = SphereGroup::new();
+    for (index, &(center, radius)) in spheres.iter().enumerate() {
+        if index > 0 {
+            list.add(Box::new(Sphere::new(center, radius, material.clone())));
+        }
+        group.add(Sphere::new(center, radius, material.clone()));
+    }
+    println!("{} spheres, {} rays, simd feature {}", spheres.len(), rays.len(), if cfg!(feature = "simd") { "on" } else { "off" });
+
+    let scalar = measure("hittable list", &rays, |rays| {
+        let mut rec = HitRecord::initialize();
+        let mut sum = 0.0;
+        for r in rays {
+            if list.hit(r, ray_t, &mut rec) {
+                sum += rec.t;
+            }
+        }
+        sum
+    });
+    let single = measure("sphere group", &rays, |rays| {
+        let mut rec = HitRecord::initialize();
+        let mut sum = 0.0;
+        for r in rays {
+            if group.hit(r, ray_t, &mut rec) {
+                sum += rec.t;
+            }
+        }
+        sum
+    });
+    let packet = measure("sphere group, packets of 4", &rays, |rays| {
+        let mut recs = [HitRecord::initialize(), HitRecord::initialize(), HitRecord::initialize(), HitRecord::initialize()];
+        let mut sum = 0.0;
+        for chunk in rays.chunks_exact(4) {
+            let packet = RayPacket { rays: [chunk[0], chunk[1], chunk[2], chunk[3]] };
+            let hits = group.hit_packet(&packet, ray_t, &mut recs);
+            for lane in 0..4 {
+                if hits[lane] {
+                    sum += recs[lane].t;
+                }
+            }
+        }
+        sum
+    });
+    println!("speedup over the hittable list: {:.2}x single, {:.2}x packets", scalar / single, scalar / packet);
+}
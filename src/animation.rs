@@ -0,0 +1,229 @@
+use std::ops::{Add, Mul, Sub};
+use crate::camera::Camera;
+use crate::hittable::{HitRecord, Hittable, HittableList};
+use crate::interval::Interval;
+use crate::ray::Ray;
+use crate::rtweekend::{Real, degrees_to_radians, gamma};
+use crate::stereo::eye_path;
+use crate::vec3::Vec3;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Interpolation {
+    Linear,
+    // smooth curve through every key, shaped by the neighbouring keys
+    CatmullRom,
+    // cubic Bezier between keys, shaped by each key's handles
+    Bezier,
+}
+
+// A value at a point in time. The handles are Bezier control points leading into and out of the key;
+// leaving them at the value eases in and out of it.
+#[derive(Clone, Copy, Debug)]
+pub struct Keyframe<T> {
+    pub time: Real,
+    pub value: T,
+    pub in_handle: T,
+    pub out_handle: T,
+}
+
+// Keyframes of one animated property, kept sorted by time. Before the first and after the last key
+// the value holds still.
+#[derive(Clone, Debug)]
+pub struct Track<T> {
+    pub keys: Vec<Keyframe<T>>,
+    pub interpolation: Interpolation,
+}
+
+impl<T> Track<T>
+where
+    T: Copy + Add<Output = T> + Sub<Output = T> + Mul<Real, Output = T>,
+{
+    pub fn new(interpolation: Interpolation) -> Self {
+        Track { keys: Vec::new(), interpolation }
+    }
+
+    pub fn key(self, time: Real, value: T) -> Self {
+        self.bezier_key(time, value, value, value)
+    }
+
+    pub fn bezier_key(mut self, time: Real, value: T, in_handle: T, out_handle: T) -> Self {
+        let index = self.keys.partition_point(|key| key.time <= time);
+        self.keys.insert(index, Keyframe { time, value, in_handle, out_handle });
+        self
+    }
+
+    pub fn sample(&self, time: Real) -> Option<T> {
+        let first = self.keys.first()?;
+        let last = self.keys.last()?;
+        if time <= first.time {
+            return Some(first.value);
+        }
+        if time >= last.time {
+            return Some(last.value);
+        }
+
+        let i = self.keys.partition_point(|key| key.time <= time) - 1;
+        let (k0, k1) = (&self.keys[i], &self.keys[i + 1]);
+        let duration = k1.time - k0.time;
+        let u = (time - k0.time) / duration;
+        Some(match self.interpolation {
+            Interpolation::Linear => k0.value + (k1.value - k0.value) * u,
+            Interpolation::CatmullRom => {
+                // tangents from the neighbouring keys, scaled to this segment's duration
+                let tangent = |j: usize| {
+                    let before = &self.keys[j.saturating_sub(1)];
+                    let after = &self.keys[(j + 1).min(self.keys.len() - 1)];
+                    (after.value - before.value) * (duration / (after.time - before.time))
+                };
+                let (m0, m1) = (tangent(i), tangent(i + 1));
+                let (u2, u3) = (u * u, u * u * u);
+                k0.value * (2.0 * u3 - 3.0 * u2 + 1.0)
+                    + m0 * (u3 - 2.0 * u2 + u)
+                    + k1.value * (-2.0 * u3 + 3.0 * u2)
+                    + m1 * (u3 - u2)
+            }
+            Interpolation::Bezier => {
+                let v = 1.0 - u;
+                k0.value * (v * v * v)
+                    + k0.out_handle * (3.0 * v * v * u)
+                    + k1.in_handle * (3.0 * v * u * u)
+                    + k1.value * (u * u * u)
+            }
+        })
+    }
+}
+
+// Keyframed camera settings, each track optional so the rest of the camera stays as configured.
+#[derive(Clone, Debug, Default)]
+pub struct CameraAnimation {
+    pub look_from: Option<Track<Vec3>>,
+    pub look_at: Option<Track<Vec3>>,
+    pub vfov: Option<Track<Real>>,
+    pub focus_dist: Option<Track<Real>>,
+}
+
+// Frame range to render, inclusive, and how frames map onto animation time.
+#[derive(Clone, Debug)]
+pub struct Sequence {
+    pub first_frame: u32,
+    pub last_frame: u32,
+    pub fps: Real,
+    // how long the shutter stays open, as a fraction of the frame: 0.5 is a 180 degree shutter, 0 turns
+    // motion blur off
+    pub shutter: Real,
+    // frames are written to {output_prefix}_0001.png and so on
+    pub output_prefix: String,
+}
+
+impl Default for Sequence {
+    fn default() -> Self {
+        Sequence { first_frame: 1, last_frame: 24, fps: 24.0, shutter: 0.5, output_prefix: String::from("frame") }
+    }
+}
+
+impl CameraAnimation {
+    pub fn apply(&self, camera: &mut Camera, time: Real) {
+        if let Some(value) = self.look_from.as_ref().and_then(|track| track.sample(time)) {
+            camera.look_from = value;
+        }
+        if let Some(value) = self.look_at.as_ref().and_then(|track| track.sample(time)) {
+            camera.look_at = value;
+        }
+        if let Some(value) = self.vfov.as_ref().and_then(|track| track.sample(time)) {
+            camera.vfov = value;
+        }
+        if let Some(value) = self.focus_dist.as_ref().and_then(|track| track.sample(time)) {
+            camera.focus_dist = value;
+        }
+    }
+
+    // Renders every frame of the sequence to a numbered PNG. Frame n starts at (n - 1) / fps seconds,
+    // which is when the camera is posed; objects keep moving while the shutter is open. Each frame
+    // keeps its own numbered checkpoint, so an interrupted frame is only ever resumed as itself.
+    pub fn render_sequence(&self, camera: &mut Camera, world: &HittableList, sequence: &Sequence) {
+        let checkpoint = camera.checkpoint.clone();
+        for frame in sequence.first_frame..=sequence.last_frame {
+            let time = (frame as Real - 1.0) / sequence.fps;
+            self.apply(camera, time);
+            camera.shutter_open = time;
+            camera.shutter_close = time + sequence.shutter / sequence.fps;
+            camera.output = format!("{}_{:04}.png", sequence.output_prefix, frame);
+            camera.checkpoint = checkpoint.as_ref().map(|path| eye_path(path, &format!("{:04}", frame)));
+            println!("Rendering frame {}", frame);
+            camera.render(world);
+        }
+        camera.checkpoint = checkpoint;
+    }
+}
+
+// Rotates by Euler angles in degrees, about x first, then y, then z.
+fn rotate(v: Vec3, angles: Vec3) -> Vec3 {
+    let (sx, cx) = degrees_to_radians(angles.x()).sin_cos();
+    let (sy, cy) = degrees_to_radians(angles.y()).sin_cos();
+    let (sz, cz) = degrees_to_radians(angles.z()).sin_cos();
+    let v = Vec3::new(v.x(), cx * v.y() - sx * v.z(), sx * v.y() + cx * v.z());
+    let v = Vec3::new(cy * v.x() + sy * v.z(), v.y(), -sy * v.x() + cy * v.z());
+    Vec3::new(cz * v.x() - sz * v.y(), sz * v.x() + cz * v.y(), v.z())
+}
+
+fn unrotate(v: Vec3, angles: Vec3) -> Vec3 {
+    let (sx, cx) = degrees_to_radians(-angles.x()).sin_cos();
+    let (sy, cy) = degrees_to_radians(-angles.y()).sin_cos();
+    let (sz, cz) = degrees_to_radians(-angles.z()).sin_cos();
+    let v = Vec3::new(cz * v.x() - sz * v.y(), sz * v.x() + cz * v.y(), v.z());
+    let v = Vec3::new(cy * v.x() + sy * v.z(), v.y(), -sy * v.x() + cy * v.z());
+    Vec3::new(v.x(), cx * v.y() - sx * v.z(), sx * v.y() + cx * v.z())
+}
+
+// Moves an object over time: it is scaled uniformly, rotated by Euler angles in degrees and then
+// translated, each following its own track and evaluated at the time of every ray for motion blur.
+pub struct AnimatedTransform {
+    object: Box<dyn Hittable>,
+    pub translation: Track<Vec3>,
+    pub rotation: Track<Vec3>,
+    pub scale: Track<Real>,
+}
+
+impl AnimatedTransform {
+    pub fn new(object: Box<dyn Hittable>) -> Self {
+        AnimatedTransform {
+            object,
+            translation: Track::new(Interpolation::Linear),
+            rotation: Track::new(Interpolation::Linear),
+            scale: Track::new(Interpolation::Linear),
+        }
+    }
+}
+
+impl Hittable for AnimatedTransform {
+    fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
+        let time = r.time();
+        let translation = self.translation.sample(time).unwrap_or(Vec3::new(0.0, 0.0, 0.0));
+        let rotation = self.rotation.sample(time).unwrap_or(Vec3::new(0.0, 0.0, 0.0));
+        let scale = self.scale.sample(time).unwrap_or(1.0);
+
+        // scaling the direction along with the origin keeps the ray parameter t the same in both spaces
+        let origin = unrotate(r.origin() - translation, rotation) / scale;
+        let direction = unrotate(r.direction(), rotation) / scale;
+        let object_ray = Ray::with_time(origin, direction, time);
+        if !self.object.hit(&object_ray, ray_t, rec) {
+            return false;
+        }
+
+        // rotating keeps lengths, so each coordinate of the error is bounded by the length of the error
+        // in object space, plus the rounding of this transform and of the inverse one applied to rays
+        // spawned from the hit, which must still start outside the object's error bounds
+        let bound = scale.abs() * ((1.0 + gamma(20)) * rec.p_error.length() + gamma(20) * rec.p.length());
+        rec.p = scale * rotate(rec.p, rotation) + translation;
+        rec.p_error = Vec3::new(bound, bound, bound) + gamma(2) * (translation.abs() + rec.p.abs());
+        rec.normal = rotate(rec.normal, rotation);
+        rec.shading_normal = rotate(rec.shading_normal, rotation);
+        rec.dpdu = scale * rotate(rec.dpdu, rotation);
+        rec.dpdv = scale * rotate(rec.dpdv, rotation);
+        true
+    }
+
+    fn object_count(&self) -> u32 {
+        self.object.object_count()
+    }
+}
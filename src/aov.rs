@@ -0,0 +1,122 @@
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+use crate::colorspace::ColorSpace;
+use crate::film::{Film, FilmPixel};
+use crate::image_io::{write_exr, write_pfm, Image};
+use crate::vec3::Vec3;
+use crate::rtweekend::Real;
+
+// Arbitrary output variables: extra passes taken from what the camera rays hit first.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Aov {
+    // distance from the camera, 0 where nothing was hit
+    Depth,
+    // world space position of the hit
+    Position,
+    // shading normal
+    Normal,
+    Albedo,
+    // 0 for the background, then 1, 2, ... in the order the materials first appear in the image
+    MaterialId,
+    // 0 for the background, otherwise one more than the index of the object in the HittableList
+    ObjectId,
+    // light reaching the first hit straight from the lights and background
+    Direct,
+    // light reaching the first hit after bouncing off something else
+    Indirect,
+}
+
+impl Aov {
+    pub const ALL: [Aov; 8] = [
+        Aov::Depth, Aov::Position, Aov::Normal, Aov::Albedo,
+        Aov::MaterialId, Aov::ObjectId, Aov::Direct, Aov::Indirect,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Aov::Depth => "depth",
+            Aov::Position => "position",
+            Aov::Normal => "normal",
+            Aov::Albedo => "albedo",
+            Aov::MaterialId => "material_id",
+            Aov::ObjectId => "object_id",
+            Aov::Direct => "direct",
+            Aov::Indirect => "indirect",
+        }
+    }
+
+    // Channel names used for the pass's layer in an EXR file.
+    fn channels(&self) -> &'static [&'static str] {
+        match self {
+            Aov::Depth => &["Z"],
+            Aov::Position | Aov::Normal => &["X", "Y", "Z"],
+            Aov::MaterialId | Aov::ObjectId => &["id"],
+            Aov::Albedo | Aov::Direct | Aov::Indirect => &["R", "G", "B"],
+        }
+    }
+
+    // Resolves the pass from the film. Single channel passes are repeated in all three components.
+    pub fn image(&self, film: &Film) -> Image {
+        let value: fn(&FilmPixel) -> Vec3 = match self {
+            Aov::Depth => |pixel| Vec3::new(pixel.depth(), pixel.depth(), pixel.depth()),
+            Aov::Position => FilmPixel::position,
+            Aov::Normal => FilmPixel::normal,
+            Aov::Albedo => FilmPixel::albedo,
+            Aov::ObjectId => |pixel| {
+                let id = pixel.object_id.map_or(0.0, |id| id as Real + 1.0);
+                Vec3::new(id, id, id)
+            },
+            Aov::Direct => FilmPixel::direct,
+            Aov::Indirect => FilmPixel::indirect,
+            Aov::MaterialId => return material_ids(film),
+        };
+        film.to_image(value)
+    }
+}
+
+// Material keys are addresses, so they are renumbered to get ids that are stable between renders.
+fn material_ids(film: &Film) -> Image {
+    let mut ids: HashMap<usize, Real> = HashMap::new();
+    let mut image = Image::new(film.width as usize, film.height as usize);
+    for (index, pixel) in film.pixels().enumerate() {
+        let id = match pixel.material {
+            Some(key) => {
+                let next = ids.len() as Real + 1.0;
+                *ids.entry(key).or_insert(next)
+            }
+            None => 0.0,
+        };
+        image.pixels[index] = Vec3::new(id, id, id);
+    }
+    image
+}
+
+// Writes the passes next to the beauty image. A path ending in .exr gets a single multi-layer
+// EXR holding the beauty image as R, G, B and each pass as "name.channel"; anything else is
+// used as a prefix for one PFM file per pass, e.g. "aov" gives aov_depth.pfm, aov_normal.pfm, ...
+// The colour passes are in the working space, which the EXR is tagged with.
+pub fn write_aovs<P: AsRef<Path>>(film: &Film, aovs: &[Aov], working_space: ColorSpace, path: P) -> io::Result<()> {
+    let path = path.as_ref();
+    let is_exr = path.extension().is_some_and(|e| e.eq_ignore_ascii_case("exr"));
+    if !is_exr {
+        for aov in aovs {
+            let file = format!("{}_{}.pfm", path.display(), aov.name());
+            write_pfm(&aov.image(film), file)?;
+        }
+        return Ok(());
+    }
+
+    let mut channels = Vec::new();
+    let mut add_layer = |prefix: &str, names: &[&str], image: &Image| {
+        for (c, name) in names.iter().enumerate() {
+            let data = image.pixels.iter().map(|p| [p.x(), p.y(), p.z()][c] as f32).collect();
+            channels.push((format!("{}{}", prefix, name), data));
+        }
+    };
+    add_layer("", &["R", "G", "B"], &film.to_image(FilmPixel::color));
+    for aov in aovs {
+        add_layer(&format!("{}.", aov.name()), aov.channels(), &aov.image(film));
+    }
+    write_exr(film.width as usize, film.height as usize, &channels, working_space, path)
+}
@@ -0,0 +1,58 @@
+use std::rc::Rc;
+use crate::color::luminance;
+use crate::image_io::Image;
+use crate::rtweekend::{Real, degrees_to_radians, PI};
+use crate::sampling::{sample_uniform_disk_concentric, Distribution2D};
+use crate::vec3::Vec3;
+
+// Shape of the lens opening, which is also the shape out of focus highlights take.
+#[derive(Clone)]
+pub enum Aperture {
+    Circle,
+    // regular polygon formed by the iris blades, rotated counterclockwise by rotation degrees
+    Polygon { blades: u32, rotation: Real },
+    // arbitrary shape from an image
+    Mask(Rc<ApertureMask>),
+}
+
+impl Aperture {
+    // Uniformly samples a point on the opening, which fits in the unit disk.
+    pub fn sample(&self, u: (Real, Real)) -> Vec3 {
+        match self {
+            Aperture::Circle => sample_uniform_disk_concentric(u),
+            Aperture::Polygon { blades, rotation } => {
+                let blades = (*blades).max(3);
+                // every blade spans an identical triangle with the centre, so pick one and sample it
+                let scaled = u.0 * blades as Real;
+                let blade = (scaled as u32).min(blades - 1);
+                let u0 = scaled - blade as Real;
+                let angle = |k: u32| degrees_to_radians(*rotation) + 2.0 * PI * k as Real / blades as Real;
+                let (a, b) = (angle(blade), angle(blade + 1));
+                let r = u0.sqrt();
+                let s = r * (1.0 - u.1);
+                let t = r * u.1;
+                Vec3::new(s * a.cos() + t * b.cos(), s * a.sin() + t * b.sin(), 0.0)
+            }
+            Aperture::Mask(mask) => mask.sample(u),
+        }
+    }
+}
+
+// Aperture shaped like an image, transmitting light in proportion to its brightness. The image is
+// stretched over the square around the unit disk, with its top towards vup. The amount of light that
+// gets through is not changed, only where it passes.
+pub struct ApertureMask {
+    distribution: Distribution2D,
+}
+
+impl ApertureMask {
+    pub fn new(image: &Image) -> Self {
+        let func: Vec<Real> = image.pixels.iter().map(|&pixel| luminance(pixel).max(0.0)).collect();
+        ApertureMask { distribution: Distribution2D::new(&func, image.width, image.height) }
+    }
+
+    pub fn sample(&self, u: (Real, Real)) -> Vec3 {
+        let ((s, t), _) = self.distribution.sample_continuous(u);
+        Vec3::new(2.0 * s - 1.0, 1.0 - 2.0 * t, 0.0)
+    }
+}
@@ -0,0 +1,62 @@
+use crate::vec3::{Vec3, unit_vector};
+use crate::rtweekend::Real;
+
+pub struct BackgroundSample {
+    pub direction: Vec3,
+    pub radiance: Vec3,
+    pub pdf: Real,
+}
+
+// Radiance arriving from infinitely far away, seen by rays that leave the scene.
+pub trait Background {
+    fn value(&self, direction: Vec3) -> Vec3;
+
+    // Picks a direction towards the background for light sampling. Backgrounds that
+    // can't be sampled return None and are only reached by scattered rays.
+    fn sample(&self, _u: (Real, Real)) -> Option<BackgroundSample> {
+        None
+    }
+
+    // Solid angle density with which sample() picks a direction.
+    fn pdf(&self, _direction: Vec3) -> Real {
+        0.0
+    }
+}
+
+// Vertical blend between two colours, the classic "Ray Tracing in One Weekend" sky.
+pub struct Gradient {
+    pub bottom: Vec3,
+    pub top: Vec3,
+}
+
+impl Gradient {
+    pub fn new(bottom: Vec3, top: Vec3) -> Self { Gradient{bottom, top} }
+}
+
+impl Default for Gradient {
+    fn default() -> Self {
+        Gradient::new(Vec3::new(1.0, 1.0, 1.0), Vec3::new(0.5, 0.7, 1.0))
+    }
+}
+
+impl Background for Gradient {
+    fn value(&self, direction: Vec3) -> Vec3 {
+        let unit_direction: Vec3 = unit_vector(direction);
+        let a: Real = 0.5 * (unit_direction.y() + 1.0);
+        (1.0 - a) * self.bottom + a * self.top
+    }
+}
+
+pub struct SolidBackground {
+    pub color: Vec3,
+}
+
+impl SolidBackground {
+    pub fn new(color: Vec3) -> Self { SolidBackground{color} }
+}
+
+impl Background for SolidBackground {
+    fn value(&self, _direction: Vec3) -> Vec3 {
+        self.color
+    }
+}
@@ -0,0 +1,633 @@
+use crate::background::BackgroundSample;
+use crate::camera::View;
+use crate::film::{Features, Splat};
+use crate::hittable::HitRecord;
+use crate::integrator::{record_hit, record_miss, Integrator};
+use crate::interval::Interval;
+use crate::light::Emission;
+use crate::onb::Onb;
+use crate::ray::Ray;
+use crate::rtweekend::{Real, INFINITY, PI};
+use crate::sampler::Sampler;
+use crate::sampling::{sample_uniform_disk_concentric, sample_uniform_sphere};
+use crate::scene::Scene;
+use crate::vec3::{Vec3, dot, unit_vector};
+
+// Bidirectional path tracer: traces a path from the camera and another from a light, and joins every
+// vertex of one to every vertex of the other. Each full path could have been built in several of
+// these ways, which are weighted against each other by multiple importance sampling. Light paths find
+// the caustics glass and mirrors cast onto diffuse surfaces, which camera paths only hit by chance.
+// Joining a light path straight to the lens lands it on another pixel, so that is only done when
+// there is somewhere to splat it.
+#[derive(Clone, Copy, Debug)]
+pub struct Bdpt {
+    // bounces a full path may take between the camera and the light
+    pub max_depth: u32,
+    // light paths from the background and directional lights enter the scene through a disk of this
+    // radius around infinite_light_center, facing the light, which should cover the part of the scene
+    // they matter for
+    pub infinite_light_center: Vec3,
+    pub infinite_light_radius: Real,
+}
+
+impl Default for Bdpt {
+    fn default() -> Self {
+        Bdpt { max_depth: 5, infinite_light_center: Vec3::new(0.0, 0.0, 0.0), infinite_light_radius: 10.0 }
+    }
+}
+
+impl Integrator for Bdpt {
+    fn radiance(&self, r: Ray, scene: &Scene, sampler: &mut dyn Sampler, features: &mut Features) -> Vec3 {
+        self.trace(r, scene, sampler, features, None)
+    }
+
+    fn radiance_with_splats(&self, r: Ray, scene: &Scene, sampler: &mut dyn Sampler, features: &mut Features, view: &View, splats: &mut Vec<Splat>) -> Vec3 {
+        self.trace(r, scene, sampler, features, Some((view, splats)))
+    }
+}
+
+impl Bdpt {
+    fn trace(&self, r: Ray, scene: &Scene, sampler: &mut dyn Sampler, features: &mut Features, light_tracing: Option<(&View, &mut Vec<Splat>)>) -> Vec3 {
+        let (view, mut splats) = match light_tracing {
+            Some((view, splats)) => (Some(view), Some(splats)),
+            None => (None, None),
+        };
+        let context = Context {
+            bdpt: self,
+            scene,
+            view,
+            sampled_background: scene.background.sample((0.5, 0.5)).is_some(),
+            time: r.time(),
+        };
+
+        let mut camera = Vec::with_capacity(self.max_depth as usize + 2);
+        context.camera_subpath(r, sampler, &mut camera);
+        match camera.get(1).map(|vertex| &vertex.kind) {
+            Some(Kind::Surface { rec, r_in }) => record_hit(features, r_in, rec, &rec.material),
+            Some(Kind::Light { infinite: Some(w), .. }) => record_miss(features, scene.background.value(-*w)),
+            _ => {}
+        }
+        let mut light = Vec::with_capacity(self.max_depth as usize + 1);
+        context.light_subpath(sampler, &mut light);
+
+        let mut radiance = Vec3::new(0.0, 0.0, 0.0);
+        let mut direct = Vec3::new(0.0, 0.0, 0.0);
+        for t in 1..=camera.len() {
+            for s in 0..=light.len() {
+                // s + t vertices make a path of s + t - 2 bounces
+                if s + t < 2 || (s == 1 && t == 1) || s + t - 2 > self.max_depth as usize {
+                    continue;
+                }
+                if t == 1 && splats.is_none() {
+                    continue;
+                }
+                let (color, pixel) = context.connect(&light, &camera, s, t, sampler);
+                match (pixel, splats.as_mut()) {
+                    (Some((x, y)), Some(splats)) => splats.push(Splat { x, y, color }),
+                    _ => {
+                        radiance += color;
+                        if s + t <= 3 {
+                            direct += color;
+                        }
+                    }
+                }
+            }
+        }
+
+        if features.hit {
+            features.direct = direct;
+            features.indirect = radiance - direct;
+        }
+        radiance
+    }
+}
+
+// Where a light path starts.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Source {
+    Light(usize),
+    Background,
+}
+
+// vertices are kept in a Vec either way, so boxing the record would only add an allocation per vertex
+#[allow(clippy::large_enum_variant)]
+#[derive(Clone)]
+enum Kind {
+    Camera,
+    // lights at infinity carry the unit direction their light travels in
+    Light { source: Source, infinite: Option<Vec3> },
+    Surface { rec: HitRecord, r_in: Ray },
+}
+
+#[derive(Clone)]
+struct Vertex {
+    kind: Kind,
+    p: Vec3,
+    // throughput of the subpath up to this vertex
+    beta: Vec3,
+    // area density with which its subpath reached this vertex, and with which the other subpath would
+    // have; solid angle density for lights at infinity
+    pdf_fwd: Real,
+    pdf_rev: Real,
+    // the subpath left this vertex in a direction picked by a specular material
+    delta: bool,
+}
+
+impl Vertex {
+    fn new(kind: Kind, p: Vec3, beta: Vec3) -> Self {
+        Vertex { kind, p, beta, pdf_fwd: 0.0, pdf_rev: 0.0, delta: false }
+    }
+
+    fn infinite(&self) -> Option<Vec3> {
+        match self.kind {
+            Kind::Light { infinite, .. } => infinite,
+            _ => None,
+        }
+    }
+
+    fn normal(&self) -> Option<Vec3> {
+        match &self.kind {
+            Kind::Surface { rec, .. } => Some(rec.normal),
+            _ => None,
+        }
+    }
+
+    fn connectible(&self) -> bool {
+        match &self.kind {
+            Kind::Surface { rec, .. } => !rec.material.is_specular(),
+            _ => true,
+        }
+    }
+
+    // Unit direction from this vertex towards next.
+    fn direction_to(&self, next: &Vertex) -> Vec3 {
+        match next.infinite() {
+            Some(w) => -w,
+            None => unit_vector(next.p - self.p),
+        }
+    }
+
+    // Unit direction from prev towards this vertex.
+    fn direction_from(&self, prev: &Vertex) -> Vec3 {
+        match prev.infinite() {
+            Some(w) => w,
+            None => unit_vector(self.p - prev.p),
+        }
+    }
+
+    // Turns the solid angle density of the direction from this vertex to next into an area density at next.
+    fn convert_density(&self, pdf: Real, next: &Vertex) -> Real {
+        if next.infinite().is_some() {
+            return pdf;
+        }
+        let w = next.p - self.p;
+        let distance_squared = w.length_squared();
+        if distance_squared == 0.0 {
+            return 0.0;
+        }
+        match next.normal() {
+            Some(n) => pdf * dot(n, w).abs() / (distance_squared * distance_squared.sqrt()),
+            None => pdf / distance_squared,
+        }
+    }
+
+    // Scattering towards next, with the cosine term.
+    fn f(&self, next: &Vertex) -> Vec3 {
+        match &self.kind {
+            Kind::Surface { rec, r_in } => rec.material.eval(r_in, rec, self.direction_to(next)),
+            _ => Vec3::new(0.0, 0.0, 0.0),
+        }
+    }
+
+    // Area density with which this vertex, reached from prev, continues a path to next.
+    fn pdf(&self, context: &Context, prev: Option<&Vertex>, next: &Vertex) -> Real {
+        let pdf = match &self.kind {
+            Kind::Light { .. } => return self.pdf_light(context, next),
+            Kind::Camera => context.view.map_or(0.0, |view| view.pdf(self.direction_to(next))),
+            Kind::Surface { rec, .. } => {
+                let Some(prev) = prev else {
+                    return 0.0;
+                };
+                if rec.material.is_specular() {
+                    return 0.0;
+                }
+                let wo = self.direction_from(prev);
+                let r_in = Ray::with_time(self.p - wo, wo, rec.time);
+                rec.material.pdf(&r_in, rec, self.direction_to(next))
+            }
+        };
+        self.convert_density(pdf, next)
+    }
+
+    // Area density with which a light path from this light reaches next.
+    fn pdf_light(&self, context: &Context, next: &Vertex) -> Real {
+        let Kind::Light { source, infinite } = self.kind else {
+            return 0.0;
+        };
+        let cosine = |w: Vec3| next.normal().map_or(1.0, |n| dot(n, w).abs());
+        match infinite {
+            Some(w) => {
+                if !context.in_disk(next.p, w) {
+                    return 0.0;
+                }
+                let radius = context.bdpt.infinite_light_radius;
+                cosine(w) / (PI * radius * radius)
+            }
+            None => {
+                let to_next = next.p - self.p;
+                let distance_squared = to_next.length_squared();
+                if distance_squared == 0.0 {
+                    return 0.0;
+                }
+                let w = to_next / distance_squared.sqrt();
+                context.direction_pdf(source, w) * cosine(w) / distance_squared
+            }
+        }
+    }
+
+    // Density with which a light path starts at this light. Lights at a point are never found by
+    // chance, so theirs is 0; lights at infinity give the density of the direction instead.
+    fn pdf_light_origin(&self, context: &Context) -> Real {
+        match self.kind {
+            Kind::Light { source, infinite: Some(w) } => context.source_pdf() * context.direction_pdf(source, w),
+            _ => 0.0,
+        }
+    }
+}
+
+// Everything the subpaths of one camera sample share.
+struct Context<'a> {
+    bdpt: &'a Bdpt,
+    scene: &'a Scene<'a>,
+    // how light paths reach the image, None when they can't
+    view: Option<&'a View>,
+    // whether the background can be importance sampled, otherwise it is sampled uniformly
+    sampled_background: bool,
+    time: Real,
+}
+
+// Light from infinity passes through at most this many surfaces before its entry point is given up on.
+const MAX_CROSSINGS: u32 = 64;
+
+impl Context<'_> {
+    // Light paths pick one of the lights or the background uniformly.
+    fn source_pdf(&self) -> Real {
+        1.0 / (self.scene.lights.len() + 1) as Real
+    }
+
+    // Solid angle density with which source sends light along the unit direction w.
+    fn direction_pdf(&self, source: Source, w: Vec3) -> Real {
+        match source {
+            Source::Light(index) => self.scene.lights[index].pdf_le(w),
+            Source::Background if self.sampled_background => self.scene.background.pdf(-w),
+            Source::Background => 1.0 / (4.0 * PI),
+        }
+    }
+
+    fn sample_background(&self, u: (Real, Real)) -> Option<BackgroundSample> {
+        if self.sampled_background {
+            return self.scene.background.sample(u);
+        }
+        let direction = sample_uniform_sphere(u);
+        Some(BackgroundSample { direction, radiance: self.scene.background.value(direction), pdf: 1.0 / (4.0 * PI) })
+    }
+
+    // Whether light from infinity travelling along w passes through the disk on its way to p.
+    fn in_disk(&self, p: Vec3, w: Vec3) -> bool {
+        let offset = p - self.bdpt.infinite_light_center;
+        let across = offset - dot(offset, w) * w;
+        across.length_squared() <= self.bdpt.infinite_light_radius * self.bdpt.infinite_light_radius
+    }
+
+    // The first surface light travelling along w through p meets: the last one found looking back
+    // towards the light, turned to face it, or failing that the first one ahead.
+    fn entry(&self, p: Vec3, w: Vec3) -> Option<HitRecord> {
+        let mut ray = Ray::with_time(p, -w, self.time);
+        let mut last: Option<HitRecord> = None;
+        for _ in 0..MAX_CROSSINGS {
+            let mut rec = HitRecord::initialize();
+            if !self.scene.world.hit(&ray, Interval::new(0.0, INFINITY), &mut rec) {
+                break;
+            }
+            ray = rec.spawn_ray(-w);
+            last = Some(rec);
+        }
+        if let Some(mut rec) = last {
+            rec.front_face = !rec.front_face;
+            rec.normal = -rec.normal;
+            rec.shading_normal = -rec.shading_normal;
+            return Some(rec);
+        }
+        let mut rec = HitRecord::initialize();
+        if self.scene.world.hit(&Ray::with_time(p, w, self.time), Interval::new(0.0, INFINITY), &mut rec) {
+            return Some(rec);
+        }
+        None
+    }
+
+    fn camera_subpath(&self, r: Ray, sampler: &mut dyn Sampler, path: &mut Vec<Vertex>) {
+        let pdf = self.view.map_or(1.0, |view| view.pdf(unit_vector(r.direction())));
+        path.push(Vertex::new(Kind::Camera, r.origin(), Vec3::new(1.0, 1.0, 1.0)));
+        self.random_walk(r, None, Vec3::new(1.0, 1.0, 1.0), pdf, self.bdpt.max_depth as usize + 1, true, sampler, path);
+    }
+
+    fn light_subpath(&self, sampler: &mut dyn Sampler, path: &mut Vec<Vertex>) {
+        if self.bdpt.max_depth == 0 {
+            return;
+        }
+        let count = self.scene.lights.len() + 1;
+        let index = ((sampler.get_1d() * count as Real) as usize).min(count - 1);
+        let source = if index < self.scene.lights.len() { Source::Light(index) } else { Source::Background };
+        let u_disk = sampler.get_2d();
+        let u_direction = sampler.get_2d();
+        let emission = match source {
+            Source::Light(index) => self.scene.lights[index].sample_le(u_direction),
+            Source::Background => self.sample_background(u_direction).map(|sample| Emission {
+                origin: None,
+                direction: -sample.direction,
+                radiance: sample.radiance,
+                pdf: sample.pdf,
+            }),
+        };
+        let Some(emission) = emission else {
+            return;
+        };
+        if emission.pdf == 0.0 || emission.radiance.near_zero() {
+            return;
+        }
+        let source_pdf = self.source_pdf();
+
+        let Some(origin) = emission.origin else {
+            // light from infinity is sent through a point on the disk, from where it enters the scene
+            let w = emission.direction;
+            let onb = Onb::new(w);
+            let radius = self.bdpt.infinite_light_radius;
+            let disk = radius * sample_uniform_disk_concentric(u_disk);
+            let p = self.bdpt.infinite_light_center + disk.x() * onb.u() + disk.y() * onb.v();
+            let pdf_position = 1.0 / (PI * radius * radius);
+            let mut vertex = Vertex::new(Kind::Light { source, infinite: Some(w) }, p, emission.radiance);
+            vertex.pdf_fwd = vertex.pdf_light_origin(self);
+            path.push(vertex);
+
+            let Some(rec) = self.entry(p, w) else {
+                return;
+            };
+            let beta = emission.radiance / (source_pdf * pdf_position * emission.pdf);
+            let ray = Ray::with_time(rec.p - w, w, self.time);
+            self.random_walk(ray, Some(rec), beta, pdf_position, self.bdpt.max_depth as usize, false, sampler, path);
+            // the entry point is picked by its position on the disk, not by a direction
+            if let Some(first) = path.get_mut(1) {
+                first.pdf_fwd = pdf_position * first.normal().map_or(1.0, |n| dot(n, w).abs());
+            }
+            return;
+        };
+
+        let mut vertex = Vertex::new(Kind::Light { source, infinite: None }, origin, emission.radiance);
+        vertex.pdf_fwd = source_pdf;
+        path.push(vertex);
+        let beta = emission.radiance / (source_pdf * emission.pdf);
+        let ray = Ray::with_time(origin, emission.direction, self.time);
+        self.random_walk(ray, None, beta, emission.pdf, self.bdpt.max_depth as usize, false, sampler, path);
+    }
+
+    // Extends path from its last vertex along ray, whose direction was picked with solid angle density
+    // pdf, adding at most max_vertices vertices. first is where ray is already known to land. Camera
+    // paths that leave the scene end on a vertex for the background.
+    #[allow(clippy::too_many_arguments)]
+    fn random_walk(&self, mut ray: Ray, mut first: Option<HitRecord>, mut beta: Vec3, pdf: Real, max_vertices: usize, camera: bool, sampler: &mut dyn Sampler, path: &mut Vec<Vertex>) {
+        let mut pdf_fwd = pdf;
+        for bounce in 0..max_vertices {
+            let mut rec = match first.take() {
+                Some(rec) => rec,
+                None => {
+                    let mut rec = HitRecord::initialize();
+                    if !self.scene.world.hit(&ray, Interval::new(0.0, INFINITY), &mut rec) {
+                        if camera {
+                            let w = unit_vector(ray.direction());
+                            let mut vertex = Vertex::new(Kind::Light { source: Source::Background, infinite: Some(-w) }, ray.origin() + w, beta);
+                            vertex.pdf_fwd = pdf_fwd;
+                            path.push(vertex);
+                        }
+                        return;
+                    }
+                    rec
+                }
+            };
+            let p = rec.p;
+            let material = rec.material.clone();
+            if bounce + 1 == max_vertices {
+                let mut vertex = Vertex::new(Kind::Surface { rec, r_in: ray }, p, beta);
+                vertex.pdf_fwd = path.last().unwrap().convert_density(pdf_fwd, &vertex);
+                path.push(vertex);
+                return;
+            }
+
+            let mut scattered = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 0.0));
+            let mut attenuation = Vec3::new(0.0, 0.0, 0.0);
+            let scatters = material.scatter(&ray, &mut rec, &mut attenuation, &mut scattered, sampler);
+            let wi = unit_vector(scattered.direction());
+            let wo = unit_vector(ray.direction());
+            // specular directions have no density, which the weights treat as a special case
+            let specular = material.is_specular();
+            let (pdf_next, pdf_back) = if specular {
+                (0.0, 0.0)
+            } else {
+                let back = Ray::with_time(p + wi, -wi, rec.time);
+                (material.pdf(&ray, &rec, wi), material.pdf(&back, &rec, -wo))
+            };
+
+            let mut vertex = Vertex::new(Kind::Surface { rec, r_in: ray }, p, beta);
+            let prev = path.last_mut().unwrap();
+            vertex.pdf_fwd = prev.convert_density(pdf_fwd, &vertex);
+            if !scatters || (!specular && pdf_next == 0.0) {
+                path.push(vertex);
+                return;
+            }
+            vertex.delta = specular;
+            prev.pdf_rev = vertex.convert_density(pdf_back, prev);
+            path.push(vertex);
+
+            beta = beta * attenuation;
+            ray = scattered;
+            pdf_fwd = pdf_next;
+        }
+    }
+
+    // Light carried by the path made of the first s vertices of the light path and the first t of the
+    // camera path, weighted against the other ways of building it. Paths joined straight to the lens
+    // also return the pixel they land on.
+    fn connect(&self, light: &[Vertex], camera: &[Vertex], s: usize, t: usize, sampler: &mut dyn Sampler) -> (Vec3, Option<(u32, u32)>) {
+        let black = (Vec3::new(0.0, 0.0, 0.0), None);
+        // the background only ends camera paths
+        if t > 1 && s != 0 && matches!(camera[t - 1].kind, Kind::Light { .. }) {
+            return black;
+        }
+
+        let mut sampled = None;
+        let mut pixel = None;
+        let color = if s == 0 {
+            let pt = &camera[t - 1];
+            match pt.kind {
+                Kind::Light { infinite: Some(w), .. } => pt.beta * self.scene.background.value(-w),
+                _ => return black,
+            }
+        } else if t == 1 {
+            // the light path's end seen through the lens
+            let qs = &light[s - 1];
+            let (Kind::Surface { rec, .. }, Some(view)) = (&qs.kind, self.view) else {
+                return black;
+            };
+            if !qs.connectible() {
+                return black;
+            }
+            let Some(sample) = view.sample(qs.p, sampler.get_2d()) else {
+                return black;
+            };
+            if sample.pdf == 0.0 || sample.importance == 0.0 {
+                return black;
+            }
+            let importance = sample.importance / sample.pdf;
+            let vertex = Vertex::new(Kind::Camera, qs.p + sample.distance * sample.direction, Vec3::new(importance, importance, importance));
+            let color = qs.beta * qs.f(&vertex) * vertex.beta;
+            if color.near_zero() || self.scene.occluded(rec, sample.direction, sample.distance) {
+                return black;
+            }
+            pixel = Some((sample.x, sample.y));
+            sampled = Some(vertex);
+            color
+        } else if s == 1 {
+            // the camera path's end sampling a light, as in next event estimation
+            let pt = &camera[t - 1];
+            let Kind::Surface { rec, .. } = &pt.kind else {
+                return black;
+            };
+            if !pt.connectible() {
+                return black;
+            }
+            let count = self.scene.lights.len() + 1;
+            let index = ((sampler.get_1d() * count as Real) as usize).min(count - 1);
+            let u = sampler.get_2d();
+            let (source, sample) = if index < self.scene.lights.len() {
+                let sample = self.scene.lights[index].sample_li(rec.p, u);
+                (Source::Light(index), sample.map(|sample| (sample.direction, sample.radiance, sample.pdf, sample.distance)))
+            } else {
+                let sample = self.sample_background(u);
+                (Source::Background, sample.map(|sample| (sample.direction, sample.radiance, sample.pdf, INFINITY)))
+            };
+            let Some((direction, radiance, pdf, distance)) = sample else {
+                return black;
+            };
+            if pdf == 0.0 || radiance.near_zero() {
+                return black;
+            }
+            let (infinite, p) = if distance.is_infinite() {
+                (Some(-direction), rec.p + direction)
+            } else {
+                (None, rec.p + distance * direction)
+            };
+            let mut vertex = Vertex::new(Kind::Light { source, infinite }, p, radiance / (pdf * self.source_pdf()));
+            vertex.pdf_fwd = vertex.pdf_light_origin(self);
+            let color = pt.beta * pt.f(&vertex) * vertex.beta;
+            if color.near_zero() || self.scene.occluded(rec, direction, distance) {
+                return black;
+            }
+            sampled = Some(vertex);
+            color
+        } else {
+            let (qs, pt) = (&light[s - 1], &camera[t - 1]);
+            let Kind::Surface { rec, .. } = &pt.kind else {
+                return black;
+            };
+            if !qs.connectible() || !pt.connectible() {
+                return black;
+            }
+            let color = qs.beta * qs.f(pt) * pt.f(qs) * pt.beta;
+            let to_light = qs.p - pt.p;
+            let distance_squared = to_light.length_squared();
+            if color.near_zero() || distance_squared == 0.0 {
+                return black;
+            }
+            let distance = distance_squared.sqrt();
+            if self.scene.occluded(rec, to_light / distance, distance) {
+                return black;
+            }
+            color / distance_squared
+        };
+
+        let weight = self.mis_weight(light, camera, sampled.as_ref(), s, t);
+        (weight * color, pixel)
+    }
+
+    // Balance heuristic weight of building the path with s light and t camera vertices, against every
+    // other split of the same path. The ratios of the densities of neighbouring splits are chained
+    // outwards from the connection, with the densities at its ends changed to the ones the joined path
+    // gives them.
+    fn mis_weight(&self, light: &[Vertex], camera: &[Vertex], sampled: Option<&Vertex>, s: usize, t: usize) -> Real {
+        if s + t == 2 {
+            return 1.0;
+        }
+        // a specular vertex gives the one it sampled a density of 0, which doesn't count towards the
+        // ratios; any other 0 means the other subpath can't reach the vertex, as when it lies outside
+        // the disk light from infinity is sent through. Vertices that were actually sampled only have a
+        // density of 0 after a specular vertex or on a light at a point.
+        let remap_rev = |pdf: Real, delta: bool| if pdf == 0.0 && delta { 1.0 } else { pdf };
+        let remap_fwd = |pdf: Real| if pdf != 0.0 { pdf } else { 1.0 };
+
+        let qs = match s {
+            0 => None,
+            1 => sampled,
+            _ => Some(&light[s - 1]),
+        };
+        let Some(pt) = (if t == 1 { sampled } else { Some(&camera[t - 1]) }) else {
+            return 0.0;
+        };
+        let qs_minus = if s > 1 { Some(&light[s - 2]) } else { None };
+        let pt_minus = if t > 1 { Some(&camera[t - 2]) } else { None };
+
+        let densities = |vertex: &Vertex| (vertex.pdf_fwd, vertex.pdf_rev, vertex.delta);
+        let mut camera_densities: Vec<(Real, Real, bool)> = camera[..t].iter().map(densities).collect();
+        let mut light_densities: Vec<(Real, Real, bool)> = light[..s].iter().map(densities).collect();
+        let pt_rev = match qs {
+            Some(qs) => qs.pdf(self, qs_minus, pt),
+            None => pt.pdf_light_origin(self),
+        };
+        camera_densities[t - 1] = (pt.pdf_fwd, pt_rev, false);
+        if let Some(pt_minus) = pt_minus {
+            camera_densities[t - 2].1 = match qs {
+                Some(qs) => pt.pdf(self, Some(qs), pt_minus),
+                None => pt.pdf_light(self, pt_minus),
+            };
+        }
+        if let Some(qs) = qs {
+            light_densities[s - 1] = (qs.pdf_fwd, pt.pdf(self, pt_minus, qs), false);
+            if let Some(qs_minus) = qs_minus {
+                light_densities[s - 2].1 = qs.pdf(self, Some(pt), qs_minus);
+            }
+        }
+
+        let mut sum = 0.0;
+        let mut ratio = 1.0;
+        for i in (1..t).rev() {
+            let next_delta = i + 1 < t && camera_densities[i + 1].2;
+            ratio *= remap_rev(camera_densities[i].1, next_delta) / remap_fwd(camera_densities[i].0);
+            // paths that reach the lens from the scene can only be splatted
+            if !camera_densities[i].2 && !camera_densities[i - 1].2 && (i > 1 || self.view.is_some()) {
+                sum += ratio;
+            }
+        }
+
+        // only the background can be hit by camera paths
+        let first = if s == 1 { qs } else { light.first() };
+        let hit_by_camera = first.is_some_and(|vertex| matches!(vertex.kind, Kind::Light { source: Source::Background, .. }));
+        ratio = 1.0;
+        for i in (0..s).rev() {
+            let next_delta = i + 1 < s && light_densities[i + 1].2;
+            ratio *= remap_rev(light_densities[i].1, next_delta) / remap_fwd(light_densities[i].0);
+            let delta_before = i > 0 && light_densities[i - 1].2;
+            if !light_densities[i].2 && !delta_before && (i > 0 || hit_by_camera) {
+                sum += ratio;
+            }
+        }
+        1.0 / (1.0 + sum)
+    }
+}
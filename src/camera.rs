@@ -1,26 +1,99 @@
-use crate::vec3::{Vec3, unit_vector, cross, random_in_unit_disk};
-use crate::hittable::{HittableList, HitRecord};
+use crate::vec3::{Vec3, unit_vector, cross, dot};
+use crate::sampler::{hash_to_unit, IndependentSampler, Sampler};
+use crate::filter::{BoxFilter, Filter, FilterSampler};
+use crate::projection::Projection;
+use crate::aperture::Aperture;
+use crate::physical_camera::PhysicalCamera;
+use crate::realistic_lens::RealisticLens;
+use crate::stereo::{eye_path, Stereo};
+use crate::tile::{write_checkpoint, Bounds, Checkpoint, CheckpointHeader, CropWindow};
+use crate::background::{Background, Gradient};
+use crate::light::Light;
+use crate::scene::Scene;
+use crate::integrator::{Integrator, PathTracer};
+use crate::hittable::HittableList;
 use crate::ray::Ray;
-use crate::color::write_color;
-use crate::interval::Interval;
-use crate::rtweekend;
+use crate::film::{Features, Film, FilmPixel, Splat};
+use crate::denoise::Denoiser;
+use crate::aov::{write_aovs, Aov};
+use crate::tonemap::ToneMapping;
+use crate::image_io::{is_float_format, write_image, Image};
+use crate::colorspace::ColorSpace;
 use Vec3 as point3;
 use indicatif::ProgressBar;
-use std::{fs::File, io::Write};
-use rand;
-use crate::rtweekend::degrees_to_radians;
+use std::{fs, io, rc::Rc, time::{Duration, Instant}};
+use crate::rtweekend::{Real, degrees_to_radians, PI};
+use crate::sampling::sample_uniform_disk_concentric;
 
 pub struct Camera {
-    pub aspect_ratio: f64,
+    pub aspect_ratio: Real,
     pub image_width: u32,
     pub sample_per_pixel: u32,
-    pub max_depth: u32,
-    pub vfov: f64,
+    pub vfov: Real,
     pub look_from: Vec3,
     pub look_at: Vec3,
     pub vup:Vec3,
-    pub defocus_angle: f64,
-    pub focus_dist: f64,
+    pub defocus_angle: Real,
+    pub focus_dist: Real,
+    // vfov and the defocus settings only apply to the perspective projection
+    pub projection: Projection,
+    pub aperture: Aperture,
+    // shift moves the image across the view, in units of its height; tilt turns the plane of sharp
+    // focus about the horizontal and vertical image axes, in degrees
+    pub lens_shift: (Real, Real),
+    pub lens_tilt: (Real, Real),
+    // camera rays are spread over this time interval, in seconds
+    pub shutter_open: Real,
+    pub shutter_close: Real,
+    // photographic settings that replace vfov, defocus_angle and the shutter interval when present
+    pub physical: Option<PhysicalCamera>,
+    // traces camera rays through a real lens instead, focused on focus_dist; takes the place of the
+    // projection, vfov and the thin lens settings
+    pub lens: Option<RealisticLens>,
+    // renders a left and a right eye and composes them into one image
+    pub stereo: Option<Stereo>,
+    pub background: Rc<dyn Background>,
+    pub lights: Vec<Rc<dyn Light>>,
+    // the algorithm that works out what each camera ray sees
+    pub integrator: Rc<dyn Integrator>,
+    pub sampler: Box<dyn Sampler>,
+    pub filter: Rc<dyn Filter>,
+    // progressive rendering: 0 takes all of sample_per_pixel in a single pass
+    pub samples_per_pass: u32,
+    pub time_limit: Option<Duration>,
+    // pixels stop receiving samples once their relative standard error drops below this, 0 disables it
+    pub noise_threshold: Real,
+    pub min_samples: u32,
+    // how often the partially converged image is written out between passes
+    pub preview_interval: Option<Duration>,
+    // adaptive sampling: sample_per_pixel becomes the average budget, spent where the error is highest
+    pub adaptive: bool,
+    // per pixel cap when sampling adaptively, 0 means four times sample_per_pixel
+    pub max_samples_per_pixel: u32,
+    // where to write a heatmap of the samples each pixel received
+    pub sample_heatmap: Option<String>,
+    // optional denoising of the final image, guided by albedo and normal buffers
+    pub denoiser: Option<Denoiser>,
+    // extra passes to write, and where: a .exr path for one multi-layer file, otherwise a prefix for PFM files
+    pub aovs: Vec<Aov>,
+    pub aov_output: String,
+    // exposure, white balance and tone mapping applied to the beauty image, but not to the AOVs
+    pub tone_mapping: ToneMapping,
+    // the space all scene colours are given in, and the one the beauty image is written in
+    pub working_space: ColorSpace,
+    pub output_space: ColorSpace,
+    // beauty image path, the extension picks the format: ppm, png, hdr, pfm or exr
+    pub output: String,
+    // renders only part of the image
+    pub crop_window: Option<CropWindow>,
+    // pixels are rendered in square tiles of this size, in pixels
+    pub tile_size: u32,
+    // where progress is saved between tiles, at most once per checkpoint_interval, so an interrupted
+    // render can pick up from there; the file is removed once the render finishes
+    pub checkpoint: Option<String>,
+    pub checkpoint_interval: Duration,
+    // seed the scene was built from, kept in checkpoints so they aren't resumed for another scene
+    pub scene_seed: u64,
 
     image_height: u32,
     center: Vec3,
@@ -32,28 +105,24 @@ pub struct Camera {
     w: Vec3,
     defocus_disk_u: Vec3,
     defocus_disk_v: Vec3,
+    filter_sampler: Option<FilterSampler>,
+    lens_radius: Real,
+    focus_normal: Vec3,
+    shutter: (Real, Real),
+    // how light paths land on the image, None for cameras they can't
+    view: Option<View>,
+    // which eye is being rendered, -0.5 for the left, 0.5 for the right and 0 without stereo
+    eye: Real,
+    // sideways offset of the eye for omni-directional stereo, in world units
+    eye_offset: Real,
+    // the pixels being rendered, the whole image unless there is a crop window
+    region: Bounds,
 }
 
-fn ray_color(r: Ray, depth: u32, world: &HittableList ) -> Vec3 {
-    let mut rec: HitRecord = HitRecord::initialize();
-
-    if depth <= 0 {
-        return Vec3::new(0.0, 0.0, 0.0);
-    }
-
-    if world.hit(&r, Interval::new(0.001, rtweekend::INFINITY), &mut rec) {
-        let mut scattered = Ray::new(Vec3::new(0.0, 0.0, 0.0),Vec3::new(0.0, 0.0, 0.0));
-        let mut attenuation = Vec3::new(0.0, 0.0, 0.0);
-        if rec.clone().material.scatter(&r, &mut rec, &mut attenuation, &mut scattered) {
-            return attenuation * ray_color(scattered,depth-1, world);
-        }
-        return Vec3::new(0.0, 0.0, 0.0);
-
+impl Default for Camera {
+    fn default() -> Self {
+        Self::new()
     }
-
-    let unit_direction: Vec3 = unit_vector(r.direction());
-    let a: f64 = 0.5 * (unit_direction.y() + 1.0);
-    return (1.0 - a) * Vec3::new(1.0, 1.0, 1.0) + a * Vec3::new(0.5, 0.7, 1.0);
 }
 
 impl Camera {
@@ -67,7 +136,6 @@ impl Camera {
             pixel_delta_u: Vec3::new(0.0, 0.0, 0.0), 
             pixel_delta_v: Vec3::new(0.0, 0.0, 0.0),
             sample_per_pixel: 10,
-            max_depth: 10,
             vfov: 90.0,
             look_from: Vec3::new(0.0, 0.0, -1.0),
             look_at: Vec3::new(0.0, 0.0, 0.0),
@@ -77,22 +145,73 @@ impl Camera {
             w: Vec3::new(0.0, 0.0, 0.0),
             defocus_angle: 0.0,
             focus_dist: 10.0,
+            projection: Projection::Perspective,
+            aperture: Aperture::Circle,
+            lens_shift: (0.0, 0.0),
+            lens_tilt: (0.0, 0.0),
+            shutter_open: 0.0,
+            shutter_close: 0.0,
+            physical: None,
+            lens: None,
+            stereo: None,
             defocus_disk_u: Vec3::new(0.0, 0.0, 0.0),
             defocus_disk_v: Vec3::new(0.0, 0.0, 0.0),
+            background: Rc::new(Gradient::default()),
+            lights: Vec::new(),
+            integrator: Rc::new(PathTracer::default()),
+            sampler: Box::new(IndependentSampler::new(0)),
+            filter: Rc::new(BoxFilter::default()),
+            filter_sampler: None,
+            lens_radius: 0.0,
+            focus_normal: Vec3::new(0.0, 0.0, 0.0),
+            shutter: (0.0, 0.0),
+            view: None,
+            eye: 0.0,
+            eye_offset: 0.0,
+            region: Bounds { x0: 0, y0: 0, x1: 0, y1: 0 },
+            samples_per_pass: 0,
+            time_limit: None,
+            noise_threshold: 0.0,
+            min_samples: 16,
+            preview_interval: None,
+            adaptive: false,
+            max_samples_per_pixel: 0,
+            sample_heatmap: None,
+            denoiser: None,
+            aovs: Vec::new(),
+            aov_output: String::from("image.exr"),
+            tone_mapping: ToneMapping::default(),
+            working_space: ColorSpace::LinearSrgb,
+            output_space: ColorSpace::LinearSrgb,
+            output: String::from("image.ppm"),
+            crop_window: None,
+            tile_size: 32,
+            checkpoint: None,
+            checkpoint_interval: Duration::from_secs(60),
+            scene_seed: 0,
         }
     }
 
     fn initialize(&mut self) {
-        self.image_height = (self.image_width as f64 / self.aspect_ratio) as u32;
+        self.image_height = (self.image_width as Real / self.aspect_ratio) as u32;
         if self.image_height < 1 {
             self.image_height = 1;
         }
         self.center = self.look_from;
-        
-        let theta = degrees_to_radians(self.vfov);
+        self.region = match self.crop_window {
+            Some(crop_window) => crop_window.bounds(self.image_width, self.image_height),
+            None => Bounds { x0: 0, y0: 0, x1: self.image_width, y1: self.image_height },
+        };
+
+        let aspect_ratio = self.image_width as Real / self.image_height as Real;
+        let vfov = self.physical.map_or(self.vfov, |physical| physical.vfov(aspect_ratio));
+        let theta = degrees_to_radians(vfov);
         let h = (theta/2.0).tan();
-        let viewport_height: f64 = 2.0 * h * self.focus_dist;
-        let viewport_width: f64  = viewport_height * ((self.image_width as f64) / (self.image_height as f64));
+        let viewport_height: Real = match self.projection {
+            Projection::Orthographic { height } => height,
+            _ => 2.0 * h * self.focus_dist,
+        };
+        let viewport_width: Real  = viewport_height * ((self.image_width as Real) / (self.image_height as Real));
 
         self.w = unit_vector(self.look_from - self.look_at);
         self.u = unit_vector(cross(self.vup, self.w));
@@ -102,79 +221,616 @@ impl Camera {
         let viewport_u: Vec3 = viewport_width * self.u;
         let viewport_v: Vec3 = viewport_height * -self.v;
 
-        self.pixel_delta_u = viewport_u / (self.image_width as f64);
-        self.pixel_delta_v = viewport_v / (self.image_height as f64);
+        self.pixel_delta_u = viewport_u / (self.image_width as Real);
+        self.pixel_delta_v = viewport_v / (self.image_height as Real);
 
         // Calculate the location of the upper left pixel
-        let viewport_upper_left: Vec3 = self.center - (self.focus_dist * self.w) - viewport_u / 2.0 - viewport_v / 2.0;
+        let shift = viewport_height * (self.lens_shift.0 * self.u + self.lens_shift.1 * self.v);
+        let viewport_upper_left: Vec3 = self.center - (self.focus_dist * self.w) - viewport_u / 2.0 - viewport_v / 2.0 + shift;
         self.pixel00_loc = viewport_upper_left + 0.5 * (self.pixel_delta_u + self.pixel_delta_v);
 
-        let defocus_radius = self.focus_dist * degrees_to_radians(self.defocus_angle / 2.0).tan();
-        self.defocus_disk_u = self.u * defocus_radius;
-        self.defocus_disk_v = self.v * defocus_radius;
+        // an eye moves sideways and its viewport follows part of the way, so the two views meet at the
+        // convergence distance; panoramic projections offset every ray on its own instead
+        self.eye_offset = 0.0;
+        if let Some(stereo) = &self.stereo {
+            let offset = self.eye * stereo.interocular;
+            let panoramic = !matches!(self.projection, Projection::Perspective | Projection::Orthographic { .. });
+            if panoramic && self.lens.is_none() {
+                self.eye_offset = offset;
+            } else {
+                self.center += offset * self.u;
+                self.pixel00_loc += offset * (1.0 - self.focus_dist / stereo.convergence) * self.u;
+            }
+        }
+
+        self.lens_radius = match self.physical {
+            Some(physical) => physical.aperture_radius(),
+            None => self.focus_dist * degrees_to_radians(self.defocus_angle / 2.0).tan(),
+        };
+        self.defocus_disk_u = self.u * self.lens_radius;
+        self.defocus_disk_v = self.v * self.lens_radius;
+
+        let (tilt_x, tilt_y) = (degrees_to_radians(self.lens_tilt.0), degrees_to_radians(self.lens_tilt.1));
+        self.focus_normal = unit_vector(tilt_x.cos() * tilt_y.cos() * self.w + tilt_x.sin() * self.v + tilt_y.sin() * self.u);
+
+        self.shutter = match self.physical {
+            Some(physical) => (self.shutter_open, self.shutter_open + physical.shutter_speed),
+            None => (self.shutter_open, self.shutter_close),
+        };
+
+        self.filter_sampler = Some(FilterSampler::new(self.filter.as_ref()));
+
+        // light paths can only find their way onto the image through a plain thin lens
+        let thin_lens = self.lens.is_none()
+            && matches!(self.projection, Projection::Perspective)
+            && matches!(self.aperture, Aperture::Circle)
+            && self.lens_tilt == (0.0, 0.0)
+            && self.eye_offset == 0.0;
+        self.view = if thin_lens {
+            Some(View {
+                center: self.center,
+                u: self.u,
+                v: self.v,
+                w: self.w,
+                lens_radius: self.lens_radius,
+                focus_dist: self.focus_dist,
+                upper_left: self.pixel00_loc - 0.5 * (self.pixel_delta_u + self.pixel_delta_v),
+                pixel_delta_u: self.pixel_delta_u,
+                pixel_delta_v: self.pixel_delta_v,
+                width: self.image_width,
+                height: self.image_height,
+                image_area: viewport_width * viewport_height / (self.focus_dist * self.focus_dist),
+            })
+        } else {
+            None
+        };
+
+        if let Some(lens) = &mut self.lens {
+            lens.focus(self.focus_dist);
+            lens.prepare(aspect_ratio);
+        }
 
     }
 
     pub fn render(&mut self, world: &HittableList) {
+        let background = self.background.clone();
+        let lights = self.lights.clone();
+        let scene = Scene { world, background: background.as_ref(), lights: &lights };
+
+        // both eyes share the scene, only the camera moves between them
+        let image = match self.stereo {
+            Some(stereo) => {
+                self.eye = -0.5;
+                let left = self.render_eye(&scene, Some("left"));
+                self.eye = 0.5;
+                let right = self.render_eye(&scene, Some("right"));
+                stereo.compose(&left, &right)
+            }
+            None => {
+                self.eye = 0.0;
+                self.render_eye(&scene, None)
+            }
+        };
+        if let Err(e) = self.write_beauty(&image) {
+            println!("Could not open file... {}", e)
+        }
+    }
+
+    // Renders the view from the current eye and returns its beauty image. The sample heatmap and AOVs
+    // are written straight away, with the eye's name added to their paths in stereo.
+    fn render_eye(&mut self, scene: &Scene, eye: Option<&str>) -> Image {
         self.initialize();
+        let tiles = self.region.tiles(self.tile_size);
+        let checkpoint = self.checkpoint.as_ref().map(|path| eye.map_or(path.clone(), |eye| eye_path(path, eye)));
+        let header = CheckpointHeader {
+            width: self.image_width,
+            height: self.image_height,
+            region: self.region,
+            tile_size: self.tile_size,
+            sample_per_pixel: self.sample_per_pixel,
+            sampler_seed: self.sampler.seed(),
+            scene_seed: self.scene_seed,
+            shutter: self.shutter,
+        };
+        let resumed = checkpoint.as_ref().and_then(|path| {
+            match Checkpoint::read(path, &header) {
+                Ok(checkpoint) => {
+                    println!("Resuming pass {} from {}", checkpoint.pass, path);
+                    Some(checkpoint)
+                }
+                Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+                Err(e) => {
+                    println!("Could not resume from the checkpoint, starting over... {}", e);
+                    None
+                }
+            }
+        });
+        let (mut film, mut pass, mut resumed_pass) = match resumed {
+            Some(checkpoint) => (checkpoint.film, checkpoint.pass - 1, Some((checkpoint.plan, checkpoint.finished))),
+            None => (Film::new(self.image_width, self.image_height), 0, None),
+        };
+
+        let mut splats = Vec::new();
+        let start = Instant::now();
+        let mut last_preview = start;
+        let mut last_checkpoint = start;
+
+        loop {
+            pass += 1;
+            // a resumed pass keeps the plan it was started with, minus the tiles already done
+            let (plan, mut finished) = match resumed_pass.take() {
+                Some(state) => state,
+                None => {
+                    let plan = if self.adaptive {
+                        self.plan_adaptive_pass(&film, pass)
+                    } else {
+                        self.plan_pass(&film)
+                    };
+                    (plan, vec![false; tiles.len()])
+                }
+            };
+            if plan.iter().all(|&samples| samples == 0) {
+                break;
+            }
+
+            let pb = ProgressBar::new(tiles.len() as u64);
+            for (index, tile) in tiles.iter().enumerate() {
+                pb.inc(1);
+                if finished[index] {
+                    continue;
+                }
+                for j in tile.y0..tile.y1 {
+                    for i in tile.x0..tile.x1 {
+                        for _sample in 0..plan[(j * self.image_width + i) as usize] {
+                            let (color, features, weight) = self.sample_pixel(scene, i, j, film.pixel(i, j).samples, Some(&mut splats));
+                            film.add_sample(i, j, color, &features, weight, self.working_space);
+                            for splat in splats.drain(..) {
+                                film.add_splat(&splat);
+                            }
+                        }
+                    }
+                }
+                finished[index] = true;
+
+                if let Some(path) = &checkpoint {
+                    if last_checkpoint.elapsed() >= self.checkpoint_interval {
+                        if let Err(e) = write_checkpoint(path, &header, pass, &plan, &finished, &film) {
+                            println!("Could not write the checkpoint... {}", e);
+                        }
+                        last_checkpoint = Instant::now();
+                    }
+                }
+            }
+            pb.finish_with_message(format!("pass {} done", pass));
 
-        match File::create("image.ppm") {
-            Ok(mut buffer) => {
-                write!(&mut buffer, "P3\n{} {}\n255\n", self.image_width, self.image_height).expect("Can't write");
+            if self.time_limit.is_some_and(|limit| start.elapsed() >= limit) {
+                println!("Time limit reached after {} passes", pass);
+                break;
+            }
+            // a single eye would overwrite the stereo image, so there are no previews
+            if eye.is_none() && self.preview_interval.is_some_and(|interval| last_preview.elapsed() >= interval) {
+                let mut preview = film.clone();
+                preview.merge_splats();
+                let preview = self.cropped(&preview).unwrap_or(preview);
+                if let Err(e) = self.write_beauty(&preview.to_image(FilmPixel::color)) {
+                    println!("Could not write preview... {}", e);
+                }
+                last_preview = Instant::now();
+            }
+        }
 
-                let pb = ProgressBar::new(self.image_height as u64);
+        if let Some(path) = &checkpoint {
+            if let Err(e) = fs::remove_file(path) {
+                if e.kind() != io::ErrorKind::NotFound {
+                    println!("Could not remove the checkpoint... {}", e);
+                }
+            }
+        }
+        self.resolve(film, eye)
+    }
 
-                for  j in 0..self.image_height  {
-                    pb.inc(1);
-                    for i in 0..self.image_width {
+    // Takes one sample of pixel (i, j): its radiance, what the camera ray hit and the filter weight.
+    // With splats, light the integrator carries onto other pixels is added to them.
+    fn sample_pixel(&mut self, scene: &Scene, i: u32, j: u32, sample_index: u32, splats: Option<&mut Vec<Splat>>) -> (Vec3, Features, Real) {
+        self.sampler.start_pixel_sample(i, j, sample_index);
+        let mut features = Features::new();
+        // samples the projection doesn't cover still count, but with no weight
+        let (ray, weight) = self.get_ray(i, j);
+        let color = match (ray, splats, &self.view) {
+            (Some((r, scale)), Some(splats), Some(view)) => {
+                scale * self.integrator.radiance_with_splats(r, scene, self.sampler.as_mut(), &mut features, view, splats)
+            }
+            (Some((r, scale)), _, _) => scale * self.integrator.radiance(r, scene, self.sampler.as_mut(), &mut features),
+            (None, _, _) => Vec3::new(0.0, 0.0, 0.0),
+        };
+        (color, features, weight)
+    }
 
-                        let mut pixel_color: Vec3 = Vec3::new(0.0, 0.0, 0.0);
+    // Sets the camera up and returns the tiles of the image to render, for rendering them elsewhere
+    // with render_tile. The film they are merged into comes from new_film and is written by write_film.
+    pub fn tiles(&mut self) -> Vec<Bounds> {
+        self.initialize();
+        self.region.tiles(self.tile_size)
+    }
 
-                        for _sample in 0..self.sample_per_pixel {
-                            let r: Ray = self.get_ray(i, j);
-                            pixel_color += ray_color(r, self.max_depth, world);
-                        }
+    // The part of the image tiles may cover, once tiles has set the camera up.
+    pub fn region(&self) -> Bounds {
+        self.region
+    }
 
-                        write!(&mut buffer, "{}", write_color(&pixel_color, self.sample_per_pixel)).expect("error writing the colors");
+    pub fn new_film(&self) -> Film {
+        Film::new(self.image_width, self.image_height)
+    }
 
+    // Renders all samples of one tile in a single go, stopping early on pixels that converge, and
+    // returns a film the size of the tile. The result only depends on the sampler's seed, not on which
+    // tiles were rendered before. Adaptive sampling and stereo don't apply, and light can't be splatted
+    // onto pixels outside the tile, so integrators leave out the paths that would. The tile has to lie
+    // within region.
+    pub fn render_tile(&mut self, world: &HittableList, tile: Bounds) -> Film {
+        let background = self.background.clone();
+        let lights = self.lights.clone();
+        let scene = Scene { world, background: background.as_ref(), lights: &lights };
+
+        let mut film = Film::new(tile.width(), tile.height());
+        for j in tile.y0..tile.y1 {
+            for i in tile.x0..tile.x1 {
+                for sample in 0..self.sample_per_pixel {
+                    if self.converged(film.pixel(i - tile.x0, j - tile.y0)) {
+                        break;
                     }
+                    let (color, features, weight) = self.sample_pixel(&scene, i, j, sample, None);
+                    film.add_sample(i - tile.x0, j - tile.y0, color, &features, weight, self.working_space);
                 }
-                pb.finish_with_message("Done!");
+            }
+        }
+        film
+    }
+
+    // Writes a film put together from render_tile results the way render would have.
+    pub fn write_film(&self, film: Film) {
+        let image = self.resolve(film, None);
+        if let Err(e) = self.write_beauty(&image) {
+            println!("Could not open file... {}", e)
+        }
+    }
 
+    // Merges the splats into the finished film and crops it, writes the heatmap and AOVs and returns
+    // the beauty image.
+    fn resolve(&self, mut film: Film, eye: Option<&str>) -> Image {
+        film.merge_splats();
+        let film = self.cropped(&film).unwrap_or(film);
+
+        if let Some(path) = &self.sample_heatmap {
+            let path = eye.map_or(path.clone(), |eye| eye_path(path, eye));
+            if let Err(e) = film.write_sample_heatmap(&path) {
+                println!("Could not write the sample heatmap... {}", e)
+            }
+        }
+        if !self.aovs.is_empty() {
+            let path = eye.map_or(self.aov_output.clone(), |eye| eye_path(&self.aov_output, eye));
+            if let Err(e) = write_aovs(&film, &self.aovs, self.working_space, &path) {
+                println!("Could not write the AOVs... {}", e)
             }
-            Err(e) => {
-                println!("Could not open file... {}", e)
+        }
+        match &self.denoiser {
+            Some(denoiser) => denoiser.denoise(&film),
+            None => film.to_image(FilmPixel::color),
+        }
+    }
+
+    // The crop window cut out of the film, or None when the full size image is written.
+    fn cropped(&self, film: &Film) -> Option<Film> {
+        match self.crop_window {
+            Some(crop_window) if !crop_window.keep_canvas => {
+                Some(film.crop(self.region.x0, self.region.y0, self.region.width(), self.region.height()))
             }
+            _ => None,
         }
     }
 
-    fn get_ray(&mut self, i: u32, j: u32) -> Ray {
-        let pixel_center: Vec3 = self.pixel00_loc + ((i as f64) * self.pixel_delta_u) + ((j as f64) * self.pixel_delta_v);
-        let pixel_sample = pixel_center + self.pixel_sample_square();
-        let mut ray_origin = Vec3::new(0.0, 0.0, 0.0);
-        if self.defocus_angle <= 0.0 {
-            ray_origin = self.center;
+    // Converts the beauty image to the output space and writes it. Integer formats are tone mapped
+    // for display, float formats keep the scene linear values for compositing.
+    fn write_beauty(&self, image: &Image) -> io::Result<()> {
+        if is_float_format(&self.output) {
+            let image = self.working_space.convert_image(image, self.output_space);
+            return write_image(&image, self.output_space, &self.output);
+        }
+        let mut tone_mapping = self.tone_mapping;
+        if let Some(physical) = &self.physical {
+            tone_mapping.exposure += physical.exposure();
+        }
+        write_image(&tone_mapping.apply_image(image, self.working_space, self.output_space), self.output_space, &self.output)
+    }
+
+    // Samples each pixel gets in the next pass: the same for all until they converge or reach the cap.
+    fn plan_pass(&self, film: &Film) -> Vec<u32> {
+        let samples_per_pass = if self.samples_per_pass == 0 {
+            self.sample_per_pixel
+        } else {
+            self.samples_per_pass.min(self.sample_per_pixel)
+        };
+
+        film.pixels()
+            .enumerate()
+            .map(|(index, pixel)| {
+                if !self.in_region(index) || pixel.samples >= self.sample_per_pixel || self.converged(pixel) {
+                    0
+                } else {
+                    samples_per_pass.min(self.sample_per_pixel - pixel.samples)
+                }
+            })
+            .collect()
+    }
+
+    // After a uniform base pass of min_samples, splits the remaining budget between pixels in
+    // proportion to their estimated error. The estimate is the worst of each pixel's 3x3
+    // neighbourhood, which steadies the noisy per-pixel variance and spreads samples along edges.
+    fn plan_adaptive_pass(&self, film: &Film, pass: u32) -> Vec<u32> {
+        let cap = if self.max_samples_per_pixel == 0 {
+            4 * self.sample_per_pixel
         } else {
-            ray_origin = self.defocus_disk_sample();
+            self.max_samples_per_pixel
+        };
+        if pass == 1 {
+            let samples = self.min_samples.max(2).min(cap);
+            return (0..film.pixels().count()).map(|index| if self.in_region(index) { samples } else { 0 }).collect();
+        }
+
+        let pixel_count = (self.region.width() * self.region.height()) as u64;
+        let used: u64 = film.pixels().map(|pixel| pixel.samples as u64).sum();
+        let remaining = (self.sample_per_pixel as u64 * pixel_count).saturating_sub(used);
+        let budget = if self.samples_per_pass == 0 {
+            remaining
+        } else {
+            remaining.min(self.samples_per_pass as u64 * pixel_count)
+        };
+
+        let errors: Vec<Real> = film.pixels()
+            .enumerate()
+            .map(|(index, pixel)| {
+                if !self.in_region(index) || pixel.samples >= cap || self.converged(pixel) {
+                    0.0
+                } else {
+                    pixel.relative_error().min(1E3)
+                }
+            })
+            .collect();
+
+        let (width, height) = (self.image_width as i64, self.image_height as i64);
+        let mut smoothed = vec![0.0; errors.len()];
+        for j in 0..height {
+            for i in 0..width {
+                let index = (j * width + i) as usize;
+                if errors[index] == 0.0 {
+                    continue;
+                }
+                for (dj, di) in (-1..=1).flat_map(|dj| (-1..=1).map(move |di| (dj, di))) {
+                    let (x, y) = (i + di, j + dj);
+                    if x >= 0 && x < width && y >= 0 && y < height {
+                        smoothed[index] = Real::max(smoothed[index], errors[(y * width + x) as usize]);
+                    }
+                }
+            }
         }
-        let ray_direction = pixel_sample - ray_origin;
 
-        Ray::new(ray_origin, ray_direction)
+        let total: Real = smoothed.iter().sum();
+        if total == 0.0 || budget == 0 {
+            return vec![0; errors.len()];
+        }
+        let seed = self.sampler.seed();
+        film.pixels()
+            .zip(smoothed.iter())
+            .enumerate()
+            .map(|(index, (pixel, error))| {
+                let share = budget as Real * error / total;
+                // stochastic rounding keeps the expected total on budget, seeded so the plan repeats
+                let mut samples = share.floor() as u32;
+                if hash_to_unit(&[seed, pass as u64, index as u64]) < share.fract() {
+                    samples += 1;
+                }
+                samples.min(cap.saturating_sub(pixel.samples))
+            })
+            .collect()
+    }
 
+    fn in_region(&self, index: usize) -> bool {
+        let width = self.image_width as usize;
+        self.region.contains((index % width) as u32, (index / width) as u32)
     }
 
-    fn defocus_disk_sample(&self) -> Vec3 {
-        let p = random_in_unit_disk();
-        self.center + (p.x() * self.defocus_disk_u) + (p.y() * self.defocus_disk_v)
+    fn converged(&self, pixel: &FilmPixel) -> bool {
+        self.noise_threshold > 0.0
+            && pixel.samples >= self.min_samples
+            && pixel.relative_error() <= self.noise_threshold
     }
 
-    fn pixel_sample_square(&mut self) -> Vec3 {
-        let px: f64 = -0.5 + rand::random::<f64>();
-        let py : f64 = -0.5 + rand::random::<f64>();
+    // The camera ray through a sample of pixel (i, j) and the factor its radiance is scaled by, or None
+    // where the lens or projection sends no light; and the filter weight of the sample.
+    fn get_ray(&mut self, i: u32, j: u32) -> (Option<(Ray, Real)>, Real) {
+        let (px, py, weight) = self.pixel_sample_filter();
+        let pixel_center: Vec3 = self.pixel00_loc + ((i as Real) * self.pixel_delta_u) + ((j as Real) * self.pixel_delta_v);
+        let pixel_sample = pixel_center + (px * self.pixel_delta_u) + (py * self.pixel_delta_v);
 
-        (px * self.pixel_delta_u) + (py * self.pixel_delta_v)
+        let time = if self.shutter.1 > self.shutter.0 {
+            self.shutter.0 + self.sampler.get_1d() * (self.shutter.1 - self.shutter.0)
+        } else {
+            self.shutter.0
+        };
+
+        let s = (i as Real + 0.5 + px) / self.image_width as Real;
+        let t = (j as Real + 0.5 + py) / self.image_height as Real;
+        let lens_sample = if self.lens.is_some() { Some(self.sampler.get_2d()) } else { None };
+        if let (Some(lens), Some(u)) = (&self.lens, lens_sample) {
+            let ray = lens.generate_ray(s, t, u).map(|(r, scale)| {
+                // lens space is in millimetres, looking down +z
+                let to_world = |v: Vec3| v.x() * self.u + v.y() * self.v - v.z() * self.w;
+                let origin = self.center + lens.world_scale() * to_world(r.origin());
+                (Ray::with_time(origin, to_world(r.direction()), time), scale)
+            });
+            return (ray, weight);
+        }
 
+        let ray = match self.projection {
+            Projection::Perspective => {
+                if self.lens_radius <= 0.0 {
+                    Ray::with_time(self.center, pixel_sample - self.center, time)
+                } else {
+                    let ray_origin = self.defocus_disk_sample();
+                    let ray_direction = self.focus_point(pixel_sample) - ray_origin;
+                    Ray::with_time(ray_origin, ray_direction, time)
+                }
+            }
+            // the viewport sits on the focus plane, moved back to start the rays at the camera
+            Projection::Orthographic { .. } => Ray::with_time(pixel_sample + self.focus_dist * self.w, -self.w, time),
+            projection => {
+                let aspect_ratio = self.image_width as Real / self.image_height as Real;
+                let Some(d) = projection.direction(s, t, aspect_ratio) else {
+                    return (None, weight);
+                };
+                let to_world = |v: Vec3| v.x() * self.u + v.y() * self.v + v.z() * self.w;
+                match &self.stereo {
+                    Some(stereo) if self.eye_offset != 0.0 => {
+                        // the eye sits on a circle, sideways to the horizontal part of the direction,
+                        // and turns in to meet the other eye's ray at the convergence distance
+                        let sideways = Vec3::new(-d.z(), 0.0, d.x());
+                        let origin = self.center + self.eye_offset * to_world(sideways);
+                        let target = self.center + stereo.convergence * to_world(d);
+                        Ray::with_time(origin, target - origin, time)
+                    }
+                    _ => Ray::with_time(self.center, to_world(d), time),
+                }
+            }
+        };
+        (Some((ray, 1.0)), weight)
+    }
+
+    // Where the pinhole ray through a point of the viewport meets the plane of sharp focus, which
+    // is the viewport itself unless the lens is tilted.
+    fn focus_point(&self, pixel_sample: Vec3) -> Vec3 {
+        if self.lens_tilt == (0.0, 0.0) {
+            return pixel_sample;
+        }
+        let direction = pixel_sample - self.center;
+        let denominator = dot(direction, self.focus_normal);
+        let t = dot(-self.focus_dist * self.w, self.focus_normal) / denominator;
+        if denominator.abs() < 1E-8 || t <= 0.0 {
+            return pixel_sample;
+        }
+        self.center + t * direction
+    }
+
+    fn defocus_disk_sample(&mut self) -> Vec3 {
+        let p = self.aperture.sample(self.sampler.get_2d());
+        self.center + (p.x() * self.defocus_disk_u) + (p.y() * self.defocus_disk_v)
+    }
+
+    // Offset from the pixel centre in pixels, and the weight of the sample.
+    fn pixel_sample_filter(&mut self) -> (Real, Real, Real) {
+        let u = self.sampler.get_2d();
+        match &self.filter_sampler {
+            Some(filter_sampler) => filter_sampler.sample(self.filter.as_ref(), u),
+            None => (-0.5 + u.0, -0.5 + u.1, 1.0),
+        }
     }
 }
 
+// The camera as seen from the scene, for integrators that trace paths from the lights and join them
+// up with the lens: a pinhole or a thin lens with a round aperture, imaging the plane of focus.
+// Cameras with other projections, a realistic lens, a tilted lens or a shaped aperture have none.
+#[derive(Clone, Copy, Debug)]
+pub struct View {
+    center: Vec3,
+    u: Vec3,
+    v: Vec3,
+    w: Vec3,
+    lens_radius: Real,
+    focus_dist: Real,
+    // corner of the image on the plane of focus, and the size of a pixel there
+    upper_left: Vec3,
+    pixel_delta_u: Vec3,
+    pixel_delta_v: Vec3,
+    width: u32,
+    height: u32,
+    // area of the image scaled onto a plane one unit in front of the lens
+    image_area: Real,
+}
 
+// A point on the lens that sees a point of the scene.
+pub struct ViewSample {
+    // unit vector from the point of the scene towards the lens, and the distance to it
+    pub direction: Vec3,
+    pub distance: Real,
+    // importance the camera gives light arriving along direction, before dividing by pdf
+    pub importance: Real,
+    // solid angle density of direction at the point of the scene
+    pub pdf: Real,
+    // the pixel the light lands on
+    pub x: u32,
+    pub y: u32,
+}
+
+impl View {
+    fn lens_area(&self) -> Real {
+        if self.lens_radius > 0.0 { PI * self.lens_radius * self.lens_radius } else { 1.0 }
+    }
+
+    // Pixel a ray leaving the lens at origin along the unit direction lands on, None outside the image.
+    pub fn raster(&self, origin: Vec3, direction: Vec3) -> Option<(u32, u32)> {
+        let cos_theta = dot(direction, -self.w);
+        if cos_theta <= 0.0 {
+            return None;
+        }
+        // origin lies on the lens, focus_dist from the plane of focus
+        let offset = origin + (self.focus_dist / cos_theta) * direction - self.upper_left;
+        let x = dot(offset, self.pixel_delta_u) / self.pixel_delta_u.length_squared();
+        let y = dot(offset, self.pixel_delta_v) / self.pixel_delta_v.length_squared();
+        if x < 0.0 || y < 0.0 || x >= self.width as Real || y >= self.height as Real {
+            return None;
+        }
+        Some((x as u32, y as u32))
+    }
+
+    // Importance of a ray leaving the lens along the unit direction, spread so that it integrates to
+    // one over the image and the lens.
+    pub fn importance(&self, direction: Vec3) -> Real {
+        let cos_theta = dot(direction, -self.w);
+        if cos_theta <= 0.0 {
+            return 0.0;
+        }
+        let cos2_theta = cos_theta * cos_theta;
+        1.0 / (self.image_area * self.lens_area() * cos2_theta * cos2_theta)
+    }
+
+    // Solid angle density with which camera rays leave the lens along the unit direction. Camera rays
+    // are taken to spread over the whole image, not only the pixel being sampled.
+    pub fn pdf(&self, direction: Vec3) -> Real {
+        let cos_theta = dot(direction, -self.w);
+        if cos_theta <= 0.0 {
+            return 0.0;
+        }
+        1.0 / (self.image_area * cos_theta * cos_theta * cos_theta)
+    }
+
+    // Picks a point on the lens to see p from, None when p doesn't show up on the image.
+    pub fn sample(&self, p: Vec3, u: (Real, Real)) -> Option<ViewSample> {
+        let disk = self.lens_radius * sample_uniform_disk_concentric(u);
+        let origin = self.center + disk.x() * self.u + disk.y() * self.v;
+        let to_lens = origin - p;
+        let distance = to_lens.length();
+        if distance == 0.0 {
+            return None;
+        }
+        let direction = to_lens / distance;
+        let (x, y) = self.raster(origin, -direction)?;
+        let cos_theta = dot(-direction, -self.w);
+        Some(ViewSample {
+            direction,
+            distance,
+            importance: self.importance(-direction),
+            pdf: distance * distance / (cos_theta * self.lens_area()),
+            x,
+            y,
+        })
+    }
+}
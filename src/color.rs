@@ -1,45 +1,83 @@
 use crate::vec3::Vec3;
 use crate::interval::Interval;
-use rand::{random, Rng};
+use rand::Rng;
+use crate::rtweekend::Real;
 
 
 pub fn write_color(pixel_color: &Vec3, samples_per_pixel: u32) -> String {
-    let mut r: f64 = pixel_color.x();
-    let mut g: f64 = pixel_color.y();
-    let mut b: f64 = pixel_color.z();
+    let [r, g, b] = encode_color(pixel_color, samples_per_pixel);
+    format!("{} {} {}\n", r, g, b)
+}
+
+// Averages the accumulated color and encodes it as 8 bit sRGB.
+pub fn encode_color(pixel_color: &Vec3, samples_per_pixel: u32) -> [u8; 3] {
+    let mut r: Real = pixel_color.x();
+    let mut g: Real = pixel_color.y();
+    let mut b: Real = pixel_color.z();
 
-    let scale: f64 = 1.0 / (samples_per_pixel as f64);
+    let scale: Real = 1.0 / (samples_per_pixel as Real);
     r *= scale;
     g *= scale;
     b *= scale;
 
-    r = linear_to_gamma(r);
-    g = linear_to_gamma(g);
-    b = linear_to_gamma(b);
+    r = linear_to_srgb(r);
+    g = linear_to_srgb(g);
+    b = linear_to_srgb(b);
 
     let intensity = Interval::new(0.000, 0.999);
 
-    let result = format!("{} {} {}\n",
-      (255.999 * intensity.clamp(r)) as u8,
-      (255.999 * intensity.clamp(g)) as u8,
-      (255.999 * intensity.clamp(b)) as u8);
-    return result;
+    [(255.999 * intensity.clamp(r)) as u8,
+     (255.999 * intensity.clamp(g)) as u8,
+     (255.999 * intensity.clamp(b)) as u8]
 }
 
-pub fn random_color() -> Vec3 {
-    let r = rand::thread_rng().gen_range(0.0..=1.0);
-    let g = rand::thread_rng().gen_range(0.0..=1.0);
-    let b = rand::thread_rng().gen_range(0.0..=1.0);
-    Vec3::new(r, g, b)
+pub fn random_color<R: Rng>(rng: &mut R) -> Vec3 {
+    random_color_min_max(rng, 0.0, 1.0)
 }
 
-pub fn random_color_min_max(min: f64, max: f64) -> Vec3 {
-    let r = rand::thread_rng().gen_range(min..=max);
-    let g = rand::thread_rng().gen_range(min..=max);
-    let b = rand::thread_rng().gen_range(min..=max);
+pub fn random_color_min_max<R: Rng>(rng: &mut R, min: Real, max: Real) -> Vec3 {
+    let r = rng.gen_range(min..=max);
+    let g = rng.gen_range(min..=max);
+    let b = rng.gen_range(min..=max);
     Vec3::new(r, g, b)
 }
 
-fn linear_to_gamma(linear_component: f64) -> f64 {
-    linear_component.sqrt()
+// Rec. 709 luminance of a linear sRGB color. Colours in the working space use ColorSpace::luminance.
+pub fn luminance(color: Vec3) -> Real {
+    0.2126 * color.x() + 0.7152 * color.y() + 0.0722 * color.z()
+}
+
+// Linear sRGB primaries to CIE XYZ with a D65 white point
+pub fn rgb_to_xyz(rgb: Vec3) -> Vec3 {
+    Vec3::new(
+        0.4124564 * rgb.x() + 0.3575761 * rgb.y() + 0.1804375 * rgb.z(),
+        0.2126729 * rgb.x() + 0.7151522 * rgb.y() + 0.0721750 * rgb.z(),
+        0.0193339 * rgb.x() + 0.1191920 * rgb.y() + 0.9503041 * rgb.z(),
+    )
+}
+
+// CIE XYZ to linear sRGB primaries with a D65 white point
+pub fn xyz_to_rgb(xyz: Vec3) -> Vec3 {
+    Vec3::new(
+        3.2404542 * xyz.x() - 1.5371385 * xyz.y() - 0.4985314 * xyz.z(),
+        -0.9692660 * xyz.x() + 1.8760108 * xyz.y() + 0.0415560 * xyz.z(),
+        0.0556434 * xyz.x() - 0.2040259 * xyz.y() + 1.0572252 * xyz.z(),
+    )
+}
+
+// The piecewise sRGB transfer curve: linear segment near black, then a 2.4 power.
+pub fn linear_to_srgb(linear_component: Real) -> Real {
+    if linear_component <= 0.0031308 {
+        12.92 * linear_component
+    } else {
+        1.055 * linear_component.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+pub fn srgb_to_linear(srgb_component: Real) -> Real {
+    if srgb_component <= 0.04045 {
+        srgb_component / 12.92
+    } else {
+        ((srgb_component + 0.055) / 1.055).powf(2.4)
+    }
 }
\ No newline at end of file
@@ -0,0 +1,112 @@
+use crate::image_io::Image;
+use crate::vec3::Vec3;
+use crate::rtweekend::Real;
+
+pub type Matrix3 = [[Real; 3]; 3];
+
+pub fn transform(m: &Matrix3, v: Vec3) -> Vec3 {
+    Vec3::new(
+        m[0][0] * v.x() + m[0][1] * v.y() + m[0][2] * v.z(),
+        m[1][0] * v.x() + m[1][1] * v.y() + m[1][2] * v.z(),
+        m[2][0] * v.x() + m[2][1] * v.y() + m[2][2] * v.z(),
+    )
+}
+
+const IDENTITY: Matrix3 = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+
+// Display P3 primaries to linear sRGB and back, both with a D65 white point
+const P3_TO_SRGB: Matrix3 = [
+    [1.2249402, -0.2249402, 0.0],
+    [-0.0420570, 1.0420570, 0.0],
+    [-0.0196376, -0.0786360, 1.0982736],
+];
+const SRGB_TO_P3: Matrix3 = [
+    [0.8224620, 0.1775380, 0.0],
+    [0.0331942, 0.9668058, 0.0],
+    [0.0170826, 0.0723974, 0.9105199],
+];
+
+// ACES AP1 primaries to linear sRGB and back, with Bradford adaptation between the D60 and D65 whites
+const ACESCG_TO_SRGB: Matrix3 = [
+    [1.7050510, -0.6217921, -0.0832589],
+    [-0.1302564, 1.1408047, -0.0105483],
+    [-0.0240034, -0.1289690, 1.1529723],
+];
+const SRGB_TO_ACESCG: Matrix3 = [
+    [0.6130974, 0.3395231, 0.0473795],
+    [0.0701937, 0.9163539, 0.0134524],
+    [0.0206156, 0.1095698, 0.8698146],
+];
+
+// The RGB spaces colours can be given in. The renderer doesn't care which one the scene uses, as long
+// as every albedo, texture and light is in the same one: the camera's working space.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ColorSpace {
+    // Rec. 709 primaries with a D65 white, what colours without a stated space are taken to be
+    LinearSrgb,
+    // ACES AP1 primaries with the ACES (roughly D60) white, a wide gamut space for rendering
+    AcesCg,
+    // DCI-P3 primaries with a D65 white, as used by wide gamut displays
+    DisplayP3,
+}
+
+impl ColorSpace {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ColorSpace::LinearSrgb => "sRGB",
+            ColorSpace::AcesCg => "ACEScg",
+            ColorSpace::DisplayP3 => "Display P3",
+        }
+    }
+
+    // CIE xy chromaticities of the red, green and blue primaries and the white point, for tagging files.
+    pub fn chromaticities(&self) -> [(Real, Real); 4] {
+        match self {
+            ColorSpace::LinearSrgb => [(0.64, 0.33), (0.30, 0.60), (0.15, 0.06), (0.3127, 0.3290)],
+            ColorSpace::AcesCg => [(0.713, 0.293), (0.165, 0.830), (0.128, 0.044), (0.32168, 0.33767)],
+            ColorSpace::DisplayP3 => [(0.680, 0.320), (0.265, 0.690), (0.150, 0.060), (0.3127, 0.3290)],
+        }
+    }
+
+    fn matrix_to_srgb(self) -> &'static Matrix3 {
+        match self {
+            ColorSpace::LinearSrgb => &IDENTITY,
+            ColorSpace::AcesCg => &ACESCG_TO_SRGB,
+            ColorSpace::DisplayP3 => &P3_TO_SRGB,
+        }
+    }
+
+    fn matrix_from_srgb(self) -> &'static Matrix3 {
+        match self {
+            ColorSpace::LinearSrgb => &IDENTITY,
+            ColorSpace::AcesCg => &SRGB_TO_ACESCG,
+            ColorSpace::DisplayP3 => &SRGB_TO_P3,
+        }
+    }
+
+    // Re-expresses a linear colour in this space in another one, going through linear sRGB.
+    pub fn convert(&self, color: Vec3, to: ColorSpace) -> Vec3 {
+        if *self == to {
+            return color;
+        }
+        transform(to.matrix_from_srgb(), transform(self.matrix_to_srgb(), color))
+    }
+
+    pub fn convert_image(&self, image: &Image, to: ColorSpace) -> Image {
+        Image {
+            width: image.width,
+            height: image.height,
+            pixels: image.pixels.iter().map(|&pixel| self.convert(pixel, to)).collect(),
+        }
+    }
+
+    // Relative luminance (CIE Y) of a linear colour in this space.
+    pub fn luminance(&self, color: Vec3) -> Real {
+        let weights = match self {
+            ColorSpace::LinearSrgb => Vec3::new(0.2126390, 0.7151687, 0.0721923),
+            ColorSpace::AcesCg => Vec3::new(0.2676722, 0.6743400, 0.0579878),
+            ColorSpace::DisplayP3 => Vec3::new(0.2289746, 0.6917385, 0.0792869),
+        };
+        weights.x() * color.x() + weights.y() * color.y() + weights.z() * color.z()
+    }
+}
@@ -0,0 +1,93 @@
+use crate::film::{Film, FilmPixel};
+use crate::image_io::Image;
+use crate::vec3::Vec3;
+use crate::rtweekend::Real;
+
+// B3 spline weights of the 5x5 a-trous kernel
+const KERNEL: [Real; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+const ALBEDO_EPSILON: Real = 1E-3;
+
+// Edge-avoiding a-trous wavelet filter (Dammertz et al. 2010), guided by the albedo and normal
+// feature buffers. Texture detail is kept by filtering the colour divided by the albedo and
+// multiplying it back afterwards.
+pub struct Denoiser {
+    // each iteration doubles the kernel footprint: 5 iterations cover a 125 pixel wide area
+    pub iterations: u32,
+    // how different two colours may be before they stop being blended, halved every iteration
+    pub color_sigma: Real,
+    pub normal_sigma: Real,
+    pub albedo_sigma: Real,
+}
+
+impl Default for Denoiser {
+    fn default() -> Self {
+        Denoiser { iterations: 5, color_sigma: 0.6, normal_sigma: 0.3, albedo_sigma: 0.1 }
+    }
+}
+
+fn divide(a: Vec3, b: Vec3) -> Vec3 {
+    Vec3::new(a.x() / b.x(), a.y() / b.y(), a.z() / b.z())
+}
+
+// compresses the dynamic range so edge stopping behaves the same in shadows and highlights
+fn compress(c: Vec3) -> Vec3 {
+    Vec3::new(c.x() / (1.0 + c.x()), c.y() / (1.0 + c.y()), c.z() / (1.0 + c.z()))
+}
+
+impl Denoiser {
+    pub fn denoise(&self, film: &Film) -> Image {
+        let color = film.to_image(FilmPixel::color);
+        let albedo = film.to_image(FilmPixel::albedo);
+        let normal = film.to_image(FilmPixel::normal);
+        let (width, height) = (color.width as i64, color.height as i64);
+        let offset = Vec3::new(ALBEDO_EPSILON, ALBEDO_EPSILON, ALBEDO_EPSILON);
+
+        let mut irradiance: Vec<Vec3> = color.pixels.iter()
+            .zip(albedo.pixels.iter())
+            .map(|(&c, &a)| divide(c, a + offset))
+            .collect();
+
+        for iteration in 0..self.iterations {
+            let step = 1i64 << iteration;
+            let sigma_c = self.color_sigma / (1 << iteration) as Real;
+            let mut filtered = vec![Vec3::new(0.0, 0.0, 0.0); irradiance.len()];
+
+            for y in 0..height {
+                for x in 0..width {
+                    let p = (y * width + x) as usize;
+                    let c_p = compress(irradiance[p]);
+                    let mut sum = Vec3::new(0.0, 0.0, 0.0);
+                    let mut weight_sum = 0.0;
+
+                    for (ky, hy) in KERNEL.iter().enumerate() {
+                        for (kx, hx) in KERNEL.iter().enumerate() {
+                            let qx = x + (kx as i64 - 2) * step;
+                            let qy = y + (ky as i64 - 2) * step;
+                            if qx < 0 || qx >= width || qy < 0 || qy >= height {
+                                continue;
+                            }
+                            let q = (qy * width + qx) as usize;
+
+                            let w_c = (-(compress(irradiance[q]) - c_p).length_squared() / (sigma_c * sigma_c)).exp();
+                            let w_n = (-(normal.pixels[q] - normal.pixels[p]).length_squared() / (self.normal_sigma * self.normal_sigma)).exp();
+                            let w_a = (-(albedo.pixels[q] - albedo.pixels[p]).length_squared() / (self.albedo_sigma * self.albedo_sigma)).exp();
+                            let weight = hx * hy * w_c * w_n * w_a;
+
+                            sum += weight * irradiance[q];
+                            weight_sum += weight;
+                        }
+                    }
+
+                    filtered[p] = if weight_sum > 0.0 { sum / weight_sum } else { irradiance[p] };
+                }
+            }
+            irradiance = filtered;
+        }
+
+        let pixels = irradiance.iter()
+            .zip(albedo.pixels.iter())
+            .map(|(&e, &a)| e * (a + offset))
+            .collect();
+        Image { width: color.width, height: color.height, pixels }
+    }
+}
@@ -0,0 +1,432 @@
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::net::{TcpListener, TcpStream};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::process::{Child, Command, Stdio};
+use std::sync::{mpsc, Mutex};
+use std::thread;
+use std::time::Instant;
+use indicatif::ProgressBar;
+use crate::camera::Camera;
+use crate::film::Film;
+use crate::hittable::HittableList;
+use crate::tile::Bounds;
+
+// Bumped whenever a message changes, coordinators and workers only talk to the same version.
+pub const PROTOCOL_VERSION: u32 = 2;
+
+// Limits on what a peer can ask for, so a corrupt or hostile message is refused instead of
+// exhausting memory.
+const MAX_STRING_LENGTH: u32 = 1 << 16;
+const MAX_IMAGE_WIDTH: u32 = 1 << 16;
+
+// Builds a scene and the camera looking at it from a name and a seed, the same way on every machine.
+pub type SceneBuilder = fn(&str, u64) -> Option<(HittableList, Camera)>;
+
+// What workers need to rebuild the scene. Objects hold materials behind Rc and can't be sent as they
+// are, so the scene travels as the name and seed it is built from, plus the render settings that
+// can be changed without rebuilding it.
+#[derive(Clone, Debug)]
+pub struct SceneDescription {
+    pub name: String,
+    pub seed: u64,
+    pub image_width: u32,
+    pub sample_per_pixel: u32,
+}
+
+impl SceneDescription {
+    pub fn build(&self, builder: SceneBuilder) -> Option<(HittableList, Camera)> {
+        let (world, mut camera) = builder(&self.name, self.seed)?;
+        camera.image_width = self.image_width;
+        camera.sample_per_pixel = self.sample_per_pixel;
+        Some((world, camera))
+    }
+}
+
+// Every message is a tag byte followed by its fields in little endian. The coordinator opens with
+// Hello and the worker answers with its own; then the coordinator sends the scene, waits for Ready
+// and hands out tiles one at a time until it sends Done.
+enum Message {
+    Hello { version: u32 },
+    Scene(SceneDescription),
+    Ready,
+    Tile { index: u32, bounds: Bounds },
+    TileResult { index: u32, film: Film },
+    Done,
+    Error(String),
+}
+
+fn write_u32<W: Write>(writer: &mut W, value: u32) -> io::Result<()> {
+    writer.write_all(&value.to_le_bytes())
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn write_string<W: Write>(writer: &mut W, value: &str) -> io::Result<()> {
+    write_u32(writer, value.len() as u32)?;
+    writer.write_all(value.as_bytes())
+}
+
+fn read_string<R: Read>(reader: &mut R) -> io::Result<String> {
+    let length = read_u32(reader)?;
+    if length > MAX_STRING_LENGTH {
+        return Err(protocol_error("string is too long"));
+    }
+    let mut bytes = vec![0; length as usize];
+    reader.read_exact(&mut bytes)?;
+    String::from_utf8(bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+fn protocol_error(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+impl Message {
+    fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        match self {
+            Message::Hello { version } => {
+                writer.write_all(&[0])?;
+                write_u32(writer, *version)?;
+            }
+            Message::Scene(scene) => {
+                writer.write_all(&[1])?;
+                write_string(writer, &scene.name)?;
+                writer.write_all(&scene.seed.to_le_bytes())?;
+                write_u32(writer, scene.image_width)?;
+                write_u32(writer, scene.sample_per_pixel)?;
+            }
+            Message::Ready => writer.write_all(&[2])?,
+            Message::Tile { index, bounds } => {
+                writer.write_all(&[3])?;
+                for value in [*index, bounds.x0, bounds.y0, bounds.x1, bounds.y1] {
+                    write_u32(writer, value)?;
+                }
+            }
+            Message::TileResult { index, film } => {
+                writer.write_all(&[4])?;
+                for value in [*index, film.width, film.height] {
+                    write_u32(writer, value)?;
+                }
+                film.write_pixels(writer)?;
+            }
+            Message::Done => writer.write_all(&[5])?,
+            Message::Error(message) => {
+                writer.write_all(&[6])?;
+                write_string(writer, message)?;
+            }
+        }
+        writer.flush()
+    }
+
+    fn read<R: Read>(reader: &mut R) -> io::Result<Message> {
+        let mut tag = [0; 1];
+        reader.read_exact(&mut tag)?;
+        Ok(match tag[0] {
+            0 => Message::Hello { version: read_u32(reader)? },
+            1 => {
+                let name = read_string(reader)?;
+                let mut seed = [0; 8];
+                reader.read_exact(&mut seed)?;
+                Message::Scene(SceneDescription {
+                    name,
+                    seed: u64::from_le_bytes(seed),
+                    image_width: read_u32(reader)?,
+                    sample_per_pixel: read_u32(reader)?,
+                })
+            }
+            2 => Message::Ready,
+            3 => Message::Tile {
+                index: read_u32(reader)?,
+                bounds: Bounds { x0: read_u32(reader)?, y0: read_u32(reader)?, x1: read_u32(reader)?, y1: read_u32(reader)? },
+            },
+            4 => {
+                let index = read_u32(reader)?;
+                let (width, height) = (read_u32(reader)?, read_u32(reader)?);
+                Message::TileResult { index, film: Film::read_pixels(width, height, reader)? }
+            }
+            5 => Message::Done,
+            6 => Message::Error(read_string(reader)?),
+            _ => return Err(protocol_error("unknown message")),
+        })
+    }
+}
+
+// A connection to a worker: TCP for host:port addresses, a Unix domain socket for unix:path ones.
+enum Stream {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Stream {
+    fn connect(address: &str) -> io::Result<Stream> {
+        #[cfg(unix)]
+        if let Some(path) = address.strip_prefix("unix:") {
+            return Ok(Stream::Unix(UnixStream::connect(path)?));
+        }
+        Ok(Stream::Tcp(TcpStream::connect(address)?))
+    }
+
+    fn try_clone(&self) -> io::Result<Stream> {
+        Ok(match self {
+            Stream::Tcp(stream) => Stream::Tcp(stream.try_clone()?),
+            #[cfg(unix)]
+            Stream::Unix(stream) => Stream::Unix(stream.try_clone()?),
+        })
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.read(buf),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.write(buf),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.flush(),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.flush(),
+        }
+    }
+}
+
+enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener),
+}
+
+impl Listener {
+    fn bind(address: &str) -> io::Result<(Listener, String)> {
+        #[cfg(unix)]
+        if let Some(path) = address.strip_prefix("unix:") {
+            return Ok((Listener::Unix(UnixListener::bind(path)?), address.to_string()));
+        }
+        let listener = TcpListener::bind(address)?;
+        let local = listener.local_addr()?.to_string();
+        Ok((Listener::Tcp(listener), local))
+    }
+
+    fn accept(&self) -> io::Result<Stream> {
+        Ok(match self {
+            Listener::Tcp(listener) => Stream::Tcp(listener.accept()?.0),
+            #[cfg(unix)]
+            Listener::Unix(listener) => Stream::Unix(listener.accept()?.0),
+        })
+    }
+}
+
+// Runs a worker listening on address, a host:port (port 0 picks a free one) or unix:path. The address
+// it ends up on is printed as "listening on <address>" and nothing else goes to standard output.
+// Coordinators are served one after the other, or only the first one when once is set.
+pub fn serve(address: &str, builder: SceneBuilder, once: bool) -> io::Result<()> {
+    let (listener, local) = Listener::bind(address)?;
+    println!("listening on {}", local);
+    io::stdout().flush()?;
+    loop {
+        let stream = listener.accept()?;
+        if let Err(e) = serve_coordinator(stream, builder) {
+            eprintln!("Lost the coordinator... {}", e);
+        }
+        if once {
+            return Ok(());
+        }
+    }
+}
+
+// Serves one coordinator. Messages that can't be understood are answered with an error before the
+// connection is dropped.
+fn serve_coordinator(stream: Stream, builder: SceneBuilder) -> io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = BufWriter::new(stream);
+    let result = serve_session(&mut reader, &mut writer, builder);
+    if let Err(e) = &result {
+        if e.kind() == io::ErrorKind::InvalidData {
+            let _ = Message::Error(e.to_string()).write(&mut writer);
+        }
+    }
+    result
+}
+
+fn serve_session(reader: &mut BufReader<Stream>, writer: &mut BufWriter<Stream>, builder: SceneBuilder) -> io::Result<()> {
+    match Message::read(reader)? {
+        Message::Hello { version } if version == PROTOCOL_VERSION => {}
+        Message::Hello { version } => {
+            return Err(protocol_error(&format!("worker speaks protocol version {}, not {}", PROTOCOL_VERSION, version)));
+        }
+        _ => return Err(protocol_error("expected a hello")),
+    }
+    Message::Hello { version: PROTOCOL_VERSION }.write(writer)?;
+
+    let mut scene = None;
+    loop {
+        match Message::read(reader)? {
+            Message::Scene(description) if !(1..=MAX_IMAGE_WIDTH).contains(&description.image_width) => {
+                Message::Error(format!("image width {} is out of range", description.image_width)).write(writer)?
+            }
+            Message::Scene(description) => match description.build(builder) {
+                Some((world, mut camera)) => {
+                    camera.tiles();
+                    scene = Some((world, camera));
+                    Message::Ready.write(writer)?;
+                }
+                None => Message::Error(format!("unknown scene {}", description.name)).write(writer)?,
+            },
+            Message::Tile { index, bounds } => match &mut scene {
+                Some((world, camera)) if camera.region().encloses(&bounds) => {
+                    let film = camera.render_tile(world, bounds);
+                    Message::TileResult { index, film }.write(writer)?;
+                }
+                Some(_) => Message::Error(format!("tile {:?} is outside the image", bounds)).write(writer)?,
+                None => Message::Error(String::from("no scene to render")).write(writer)?,
+            },
+            Message::Done => return Ok(()),
+            _ => return Err(protocol_error("unexpected message")),
+        }
+    }
+}
+
+// Where a coordinator finds its workers: processes it starts itself on this machine, and workers that
+// are already running elsewhere.
+#[derive(Clone, Debug, Default)]
+pub struct Workers {
+    pub spawn: u32,
+    pub addresses: Vec<String>,
+}
+
+// Starts a copy of this program as a worker on a free local port and returns it with its address.
+// The program has to run serve when started as "<program> worker --once <address>".
+fn spawn_worker() -> io::Result<(Child, String)> {
+    let mut child = Command::new(std::env::current_exe()?)
+        .args(["worker", "--once", "127.0.0.1:0"])
+        .stdout(Stdio::piped())
+        .spawn()?;
+    let mut line = String::new();
+    if let Some(stdout) = child.stdout.take() {
+        BufReader::new(stdout).read_line(&mut line)?;
+    }
+    match line.trim().strip_prefix("listening on ") {
+        Some(address) => Ok((child, address.to_string())),
+        None => {
+            let _ = child.kill();
+            Err(protocol_error("worker did not report its address"))
+        }
+    }
+}
+
+// Connects to a worker and sets the scene up on it.
+fn start_session(address: &str, scene: &SceneDescription) -> io::Result<(BufReader<Stream>, BufWriter<Stream>)> {
+    let stream = Stream::connect(address)?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = BufWriter::new(stream);
+    Message::Hello { version: PROTOCOL_VERSION }.write(&mut writer)?;
+    match Message::read(&mut reader)? {
+        Message::Hello { version } if version == PROTOCOL_VERSION => {}
+        Message::Hello { version } => return Err(protocol_error(&format!("worker speaks protocol version {}", version))),
+        Message::Error(message) => return Err(protocol_error(&message)),
+        _ => return Err(protocol_error("expected a hello")),
+    }
+    Message::Scene(scene.clone()).write(&mut writer)?;
+    match Message::read(&mut reader)? {
+        Message::Ready => Ok((reader, writer)),
+        Message::Error(message) => Err(protocol_error(&message)),
+        _ => Err(protocol_error("expected the worker to be ready")),
+    }
+}
+
+// Hands tiles to one worker until none are left, returning the one it was working on if it fails.
+fn drive_worker(address: &str, scene: &SceneDescription, queue: &Mutex<Vec<(u32, Bounds)>>, results: mpsc::Sender<(u32, Film)>) -> io::Result<()> {
+    let (mut reader, mut writer) = start_session(address, scene)?;
+    loop {
+        let Some((index, bounds)) = queue.lock().unwrap().pop() else {
+            return Message::Done.write(&mut writer);
+        };
+        let result = Message::Tile { index, bounds }.write(&mut writer).and_then(|_| Message::read(&mut reader));
+        match result {
+            Ok(Message::TileResult { index: returned, film }) if returned == index && film.width == bounds.width() && film.height == bounds.height() => {
+                let _ = results.send((index, film));
+            }
+            other => {
+                queue.lock().unwrap().push((index, bounds));
+                return match other {
+                    Ok(Message::Error(message)) => Err(protocol_error(&message)),
+                    Ok(_) => Err(protocol_error("unexpected reply to a tile")),
+                    Err(e) => Err(e),
+                };
+            }
+        }
+    }
+}
+
+// Renders a scene on worker processes and writes it like Camera::render. The coordinator builds the
+// scene too, for its camera settings, and merges the float tiles the workers send back into one film.
+// Every tile is rendered in full by one worker and pasted in place, so the image only depends on the
+// scene and its seed, not on how many workers there are or which one finishes first.
+pub fn render_distributed(scene: &SceneDescription, builder: SceneBuilder, workers: &Workers) -> io::Result<()> {
+    let (_, mut camera) = scene.build(builder).ok_or_else(|| protocol_error("unknown scene"))?;
+    let tiles = camera.tiles();
+    let mut film = camera.new_film();
+
+    let mut children = Vec::new();
+    let mut addresses = workers.addresses.clone();
+    for _ in 0..workers.spawn {
+        let (child, address) = spawn_worker()?;
+        children.push(child);
+        addresses.push(address);
+    }
+
+    // popped from the back, so reversed to hand them out top to bottom
+    let queue = Mutex::new(tiles.iter().copied().enumerate().map(|(index, tile)| (index as u32, tile)).rev().collect::<Vec<_>>());
+    let (sender, receiver) = mpsc::channel();
+    let start = Instant::now();
+    let received = thread::scope(|scope| {
+        for address in addresses.iter() {
+            let (queue, sender) = (&queue, sender.clone());
+            scope.spawn(move || {
+                if let Err(e) = drive_worker(address, scene, queue, sender) {
+                    println!("Worker {} failed... {}", address, e);
+                }
+            });
+        }
+        drop(sender);
+
+        let pb = ProgressBar::new(tiles.len() as u64);
+        let mut received = 0;
+        for (index, tile_film) in receiver {
+            let tile = tiles[index as usize];
+            film.paste(tile.x0, tile.y0, &tile_film);
+            received += 1;
+            pb.inc(1);
+        }
+        pb.finish_with_message(format!("{} tiles in {:.1?}", received, start.elapsed()));
+        received
+    });
+
+    for child in children.iter_mut() {
+        let _ = child.kill();
+        let _ = child.wait();
+    }
+    if received < tiles.len() {
+        return Err(io::Error::other(format!("workers finished only {} of {} tiles", received, tiles.len())));
+    }
+    camera.write_film(film);
+    Ok(())
+}
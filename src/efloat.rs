@@ -0,0 +1,114 @@
+use std::ops::{Add, Div, Mul, Neg, Sub};
+use crate::rtweekend::{gamma, Real};
+
+// Arithmetic shared by plain numbers and EFloat, so the same code can work out a value quickly and
+// then, where it matters, again with its error bound.
+pub trait Number: Copy + Add<Output = Self> + Sub<Output = Self> + Mul<Output = Self> + Div<Output = Self> + Neg<Output = Self> {
+    fn sqrt(self) -> Self;
+}
+
+// What EFloat needs from the numbers it carries: Real, or with the simd feature four lanes of it,
+// which go through exactly the same operations.
+pub trait Float: Number {
+    fn splat(value: Real) -> Self;
+    fn abs(self) -> Self;
+    fn max(self, other: Self) -> Self;
+}
+
+impl Number for Real {
+    fn sqrt(self) -> Self {
+        Real::sqrt(self)
+    }
+}
+
+impl Float for Real {
+    fn splat(value: Real) -> Self {
+        value
+    }
+
+    fn abs(self) -> Self {
+        Real::abs(self)
+    }
+
+    fn max(self, other: Self) -> Self {
+        Real::max(self, other)
+    }
+}
+
+// A computed value and a bound on how far rounding has taken it from the exact result, carried
+// through each operation as in PBRT's EFloat. The value is the one plain arithmetic gives.
+#[derive(Clone, Copy, Debug)]
+pub struct EFloat<T = Real> {
+    pub v: T,
+    pub err: T,
+}
+
+impl<T: Float> EFloat<T> {
+    // A value known exactly, such as an input.
+    pub fn new(v: T) -> Self {
+        EFloat { v, err: T::splat(0.0) }
+    }
+
+    pub fn lower_bound(&self) -> T {
+        self.v - self.err
+    }
+}
+
+impl<T: Float> Add for EFloat<T> {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self {
+        let v = self.v + rhs.v;
+        EFloat { v, err: self.err + rhs.err + T::splat(gamma(1)) * v.abs() }
+    }
+}
+
+impl<T: Float> Sub for EFloat<T> {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self {
+        let v = self.v - rhs.v;
+        EFloat { v, err: self.err + rhs.err + T::splat(gamma(1)) * v.abs() }
+    }
+}
+
+impl<T: Float> Mul for EFloat<T> {
+    type Output = Self;
+
+    fn mul(self, rhs: Self) -> Self {
+        let v = self.v * rhs.v;
+        let err = self.v.abs() * rhs.err + rhs.v.abs() * self.err + self.err * rhs.err;
+        EFloat { v, err: err + T::splat(gamma(1)) * v.abs() }
+    }
+}
+
+// Unbounded once the divisor's range reaches zero.
+impl<T: Float> Div for EFloat<T> {
+    type Output = Self;
+
+    fn div(self, rhs: Self) -> Self {
+        let v = self.v / rhs.v;
+        let divisor = rhs.v.abs();
+        let smallest = (divisor - rhs.err).max(T::splat(0.0));
+        let err = (self.v.abs() * rhs.err + divisor * self.err) / (divisor * smallest);
+        EFloat { v, err: err + T::splat(gamma(1)) * v.abs() }
+    }
+}
+
+impl<T: Float> Neg for EFloat<T> {
+    type Output = Self;
+
+    fn neg(self) -> Self {
+        EFloat { v: -self.v, err: self.err }
+    }
+}
+
+impl<T: Float> Number for EFloat<T> {
+    // sqrt moves the ends of [v - err, v + err] by different amounts, so the bound is the larger move
+    fn sqrt(self) -> Self {
+        let v = self.v.sqrt();
+        let low = (self.v - self.err).max(T::splat(0.0)).sqrt();
+        let high = (self.v + self.err).sqrt();
+        EFloat { v, err: (v - low).max(high - v) + T::splat(gamma(2)) * v }
+    }
+}
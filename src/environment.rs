@@ -0,0 +1,101 @@
+use std::io;
+use std::path::Path;
+use crate::background::{Background, BackgroundSample};
+use crate::colorspace::ColorSpace;
+use crate::image_io::{load_color_image, Image};
+use crate::rtweekend::{Real, degrees_to_radians, PI};
+use crate::sampling::Distribution2D;
+use crate::vec3::{Vec3, unit_vector};
+
+// Equirectangular environment map. The centre of the image faces -Z and the top row is straight up.
+pub struct EnvironmentMap {
+    image: Image,
+    // rotation around the +Y axis, in degrees
+    rotation: Real,
+    pub intensity: Real,
+    distribution: Distribution2D,
+}
+
+impl EnvironmentMap {
+    // The image is in color_space, the camera's working space. Fails on an empty image, which has
+    // nothing to look up or sample.
+    pub fn new(image: Image, color_space: ColorSpace, rotation: Real, intensity: Real) -> io::Result<Self> {
+        if image.width == 0 || image.height == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "environment map image is empty"));
+        }
+
+        // weight each texel by its luminance and the solid angle it covers
+        let mut func = Vec::with_capacity(image.width * image.height);
+        for y in 0..image.height {
+            let sin_theta = (PI * (y as Real + 0.5) / image.height as Real).sin();
+            for x in 0..image.width {
+                func.push(color_space.luminance(image.pixel(x, y)).max(0.0) * sin_theta);
+            }
+        }
+        let distribution = Distribution2D::new(&func, image.width, image.height);
+
+        Ok(EnvironmentMap { image, rotation: degrees_to_radians(rotation), intensity, distribution })
+    }
+
+    // Loads a Radiance .hdr or .pfm file (or any other format load_image understands) for a linear
+    // sRGB working space. Other working spaces can pass load_color_image's result to new.
+    pub fn load<P: AsRef<Path>>(path: P, rotation: Real, intensity: Real) -> io::Result<Self> {
+        EnvironmentMap::new(load_color_image(path, ColorSpace::LinearSrgb)?, ColorSpace::LinearSrgb, rotation, intensity)
+    }
+
+    fn rotate(&self, v: Vec3, angle: Real) -> Vec3 {
+        let (sin, cos) = angle.sin_cos();
+        Vec3::new(cos * v.x() + sin * v.z(), v.y(), -sin * v.x() + cos * v.z())
+    }
+
+    fn direction_to_uv(&self, direction: Vec3) -> (Real, Real) {
+        let d = self.rotate(unit_vector(direction), -self.rotation);
+        let theta = d.y().clamp(-1.0, 1.0).acos();
+        let phi = d.x().atan2(-d.z());
+        let u = 0.5 + phi / (2.0 * PI);
+        (u - u.floor(), theta / PI)
+    }
+
+    fn uv_to_direction(&self, u: Real, v: Real) -> Vec3 {
+        let theta = v * PI;
+        let phi = (u - 0.5) * 2.0 * PI;
+        let d = Vec3::new(theta.sin() * phi.sin(), theta.cos(), -theta.sin() * phi.cos());
+        self.rotate(d, self.rotation)
+    }
+
+    fn lookup(&self, u: Real, v: Real) -> Vec3 {
+        let x = ((u * self.image.width as Real) as usize).min(self.image.width - 1);
+        let y = ((v * self.image.height as Real) as usize).min(self.image.height - 1);
+        self.intensity * self.image.pixel(x, y)
+    }
+}
+
+impl Background for EnvironmentMap {
+    fn value(&self, direction: Vec3) -> Vec3 {
+        let (u, v) = self.direction_to_uv(direction);
+        self.lookup(u, v)
+    }
+
+    fn sample(&self, u: (Real, Real)) -> Option<BackgroundSample> {
+        let ((su, sv), map_pdf) = self.distribution.sample_continuous(u);
+        let sin_theta = (sv * PI).sin();
+        if map_pdf == 0.0 || sin_theta == 0.0 {
+            return None;
+        }
+
+        Some(BackgroundSample {
+            direction: self.uv_to_direction(su, sv),
+            radiance: self.lookup(su, sv),
+            pdf: map_pdf / (2.0 * PI * PI * sin_theta),
+        })
+    }
+
+    fn pdf(&self, direction: Vec3) -> Real {
+        let (u, v) = self.direction_to_uv(direction);
+        let sin_theta = (v * PI).sin();
+        if sin_theta == 0.0 {
+            return 0.0;
+        }
+        self.distribution.pdf((u, v)) / (2.0 * PI * PI * sin_theta)
+    }
+}
@@ -0,0 +1,369 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Write};
+use std::path::Path;
+use crate::colorspace::ColorSpace;
+use crate::image_io::Image;
+use crate::vec3::Vec3;
+use crate::rtweekend::Real;
+
+// Surface properties seen by the camera ray, used to guide denoising and written out as AOVs.
+#[derive(Clone, Copy)]
+pub struct Features {
+    pub albedo: Vec3,
+    pub normal: Vec3,
+    // whether the camera ray hit anything; the fields below are only meaningful when it did
+    pub hit: bool,
+    // distance from the ray origin to the first hit
+    pub depth: Real,
+    pub position: Vec3,
+    // identifies the material instance, only compared for equality
+    pub material: usize,
+    pub object_id: u32,
+    // light arriving after a single bounce, and everything else
+    pub direct: Vec3,
+    pub indirect: Vec3,
+}
+
+impl Features {
+    pub fn new() -> Self {
+        Features {
+            albedo: Vec3::new(0.0, 0.0, 0.0),
+            normal: Vec3::new(0.0, 0.0, 0.0),
+            hit: false,
+            depth: 0.0,
+            position: Vec3::new(0.0, 0.0, 0.0),
+            material: 0,
+            object_id: 0,
+            direct: Vec3::new(0.0, 0.0, 0.0),
+            indirect: Vec3::new(0.0, 0.0, 0.0),
+        }
+    }
+}
+
+impl Default for Features {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Running statistics of the samples taken for one pixel.
+#[derive(Clone, Copy)]
+pub struct FilmPixel {
+    // filter weighted sum of the samples, and the sum of those weights
+    pub sum: Vec3,
+    pub weight_sum: Real,
+    pub albedo_sum: Vec3,
+    pub normal_sum: Vec3,
+    pub direct_sum: Vec3,
+    pub indirect_sum: Vec3,
+    // depth and position are only averaged over the samples that hit something
+    pub depth_sum: Real,
+    pub position_sum: Vec3,
+    pub hit_weight_sum: Real,
+    // ids can't be averaged, so the first sample that hit something decides them
+    pub material: Option<usize>,
+    pub object_id: Option<u32>,
+    pub samples: u32,
+    // light that paths started from the lights carried to this pixel, added to the samples by
+    // Film::merge_splats once all of them are taken
+    pub splat: Vec3,
+    // Welford's running mean and squared deviations of the sample luminance
+    mean: Real,
+    m2: Real,
+}
+
+impl FilmPixel {
+    fn new() -> Self {
+        FilmPixel {
+            sum: Vec3::new(0.0, 0.0, 0.0),
+            weight_sum: 0.0,
+            albedo_sum: Vec3::new(0.0, 0.0, 0.0),
+            normal_sum: Vec3::new(0.0, 0.0, 0.0),
+            direct_sum: Vec3::new(0.0, 0.0, 0.0),
+            indirect_sum: Vec3::new(0.0, 0.0, 0.0),
+            depth_sum: 0.0,
+            position_sum: Vec3::new(0.0, 0.0, 0.0),
+            hit_weight_sum: 0.0,
+            material: None,
+            object_id: None,
+            samples: 0,
+            splat: Vec3::new(0.0, 0.0, 0.0),
+            mean: 0.0,
+            m2: 0.0,
+        }
+    }
+
+    // color_space is the one the samples are in, for their luminance
+    pub fn add_sample(&mut self, color: Vec3, features: &Features, weight: Real, color_space: ColorSpace) {
+        self.sum += weight * color;
+        self.weight_sum += weight;
+        self.albedo_sum += weight * features.albedo;
+        self.normal_sum += weight * features.normal;
+        self.direct_sum += weight * features.direct;
+        self.indirect_sum += weight * features.indirect;
+        if features.hit {
+            // negative filter lobes would push these outside the range of the hits
+            self.depth_sum += weight.abs() * features.depth;
+            self.position_sum += weight.abs() * features.position;
+            self.hit_weight_sum += weight.abs();
+            if self.object_id.is_none() {
+                self.material = Some(features.material);
+                self.object_id = Some(features.object_id);
+            }
+        }
+        self.samples += 1;
+        let l = color_space.luminance(color);
+        let delta = l - self.mean;
+        self.mean += delta / self.samples as Real;
+        self.m2 += delta * (l - self.mean);
+    }
+
+    pub fn color(&self) -> Vec3 {
+        if self.weight_sum == 0.0 {
+            return Vec3::new(0.0, 0.0, 0.0);
+        }
+        self.sum / self.weight_sum
+    }
+
+    pub fn albedo(&self) -> Vec3 {
+        if self.weight_sum == 0.0 {
+            return Vec3::new(0.0, 0.0, 0.0);
+        }
+        self.albedo_sum / self.weight_sum
+    }
+
+    pub fn normal(&self) -> Vec3 {
+        if self.weight_sum == 0.0 {
+            return Vec3::new(0.0, 0.0, 0.0);
+        }
+        self.normal_sum / self.weight_sum
+    }
+
+    pub fn direct(&self) -> Vec3 {
+        if self.weight_sum == 0.0 {
+            return Vec3::new(0.0, 0.0, 0.0);
+        }
+        self.direct_sum / self.weight_sum
+    }
+
+    pub fn indirect(&self) -> Vec3 {
+        if self.weight_sum == 0.0 {
+            return Vec3::new(0.0, 0.0, 0.0);
+        }
+        self.indirect_sum / self.weight_sum
+    }
+
+    // Zero where nothing was hit.
+    pub fn depth(&self) -> Real {
+        if self.hit_weight_sum == 0.0 {
+            return 0.0;
+        }
+        self.depth_sum / self.hit_weight_sum
+    }
+
+    pub fn position(&self) -> Vec3 {
+        if self.hit_weight_sum == 0.0 {
+            return Vec3::new(0.0, 0.0, 0.0);
+        }
+        self.position_sum / self.hit_weight_sum
+    }
+
+    // Unbiased sample variance of the luminance.
+    pub fn variance(&self) -> Real {
+        if self.samples < 2 {
+            return 0.0;
+        }
+        self.m2 / (self.samples - 1) as Real
+    }
+
+    // Standard error of the mean luminance relative to the mean itself.
+    pub fn relative_error(&self) -> Real {
+        if self.samples < 2 {
+            return Real::INFINITY;
+        }
+        (self.variance() / self.samples as Real).sqrt() / (self.mean + 1E-3)
+    }
+
+    // Writes every field in a fixed little endian layout, for checkpoints. Values are stored as f64
+    // whatever the precision, so checkpoints and tiles can move between f32 and f64 builds.
+    fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let vectors = [self.sum, self.albedo_sum, self.normal_sum, self.direct_sum, self.indirect_sum, self.position_sum, self.splat];
+        let scalars = vectors.iter()
+            .flat_map(|v| [v.x(), v.y(), v.z()])
+            .chain([self.weight_sum, self.depth_sum, self.hit_weight_sum, self.mean, self.m2]);
+        for value in scalars {
+            // a no-op in f64 builds
+            #[allow(clippy::unnecessary_cast)]
+            writer.write_all(&(value as f64).to_le_bytes())?;
+        }
+        // the first sample that hit sets both ids, so they are either both present or both missing
+        match (self.material, self.object_id) {
+            (Some(material), Some(object_id)) => {
+                writer.write_all(&[1])?;
+                writer.write_all(&(material as u64).to_le_bytes())?;
+                writer.write_all(&object_id.to_le_bytes())?;
+            }
+            _ => writer.write_all(&[0])?,
+        }
+        writer.write_all(&self.samples.to_le_bytes())
+    }
+
+    fn read_from<R: Read>(reader: &mut R) -> io::Result<Self> {
+        let mut values = [0.0; 26];
+        let mut bytes = [0; 8];
+        for value in values.iter_mut() {
+            reader.read_exact(&mut bytes)?;
+            *value = f64::from_le_bytes(bytes) as Real;
+        }
+        let vector = |k: usize| Vec3::new(values[3 * k], values[3 * k + 1], values[3 * k + 2]);
+        let mut pixel = FilmPixel {
+            sum: vector(0),
+            albedo_sum: vector(1),
+            normal_sum: vector(2),
+            direct_sum: vector(3),
+            indirect_sum: vector(4),
+            position_sum: vector(5),
+            splat: vector(6),
+            weight_sum: values[21],
+            depth_sum: values[22],
+            hit_weight_sum: values[23],
+            mean: values[24],
+            m2: values[25],
+            ..FilmPixel::new()
+        };
+        let mut flag = [0; 1];
+        reader.read_exact(&mut flag)?;
+        if flag[0] != 0 {
+            reader.read_exact(&mut bytes)?;
+            pixel.material = Some(u64::from_le_bytes(bytes) as usize);
+            let mut id = [0; 4];
+            reader.read_exact(&mut id)?;
+            pixel.object_id = Some(u32::from_le_bytes(id));
+        }
+        let mut samples = [0; 4];
+        reader.read_exact(&mut samples)?;
+        pixel.samples = u32::from_le_bytes(samples);
+        Ok(pixel)
+    }
+}
+
+// Light reaching pixel x, y from a path that didn't start there.
+#[derive(Clone, Copy, Debug)]
+pub struct Splat {
+    pub x: u32,
+    pub y: u32,
+    pub color: Vec3,
+}
+
+// Floating point framebuffer that samples are accumulated into.
+#[derive(Clone)]
+pub struct Film {
+    pub width: u32,
+    pub height: u32,
+    pixels: Vec<FilmPixel>,
+}
+
+impl Film {
+    pub fn new(width: u32, height: u32) -> Self {
+        Film { width, height, pixels: vec![FilmPixel::new(); (width * height) as usize] }
+    }
+
+    pub fn pixel(&self, x: u32, y: u32) -> &FilmPixel {
+        &self.pixels[(y * self.width + x) as usize]
+    }
+
+    pub fn pixel_mut(&mut self, x: u32, y: u32) -> &mut FilmPixel {
+        &mut self.pixels[(y * self.width + x) as usize]
+    }
+
+    pub fn pixels(&self) -> impl Iterator<Item = &FilmPixel> {
+        self.pixels.iter()
+    }
+
+    pub fn add_sample(&mut self, x: u32, y: u32, color: Vec3, features: &Features, weight: Real, color_space: ColorSpace) {
+        self.pixel_mut(x, y).add_sample(color, features, weight, color_space);
+    }
+
+    pub fn add_splat(&mut self, splat: &Splat) {
+        self.pixel_mut(splat.x, splat.y).splat += splat.color;
+    }
+
+    // Adds the splats to the pixel colours and clears them. Every sample taken anywhere on the film
+    // could have splatted onto any pixel, so they are averaged over the samples of the whole film.
+    // Pixels without samples, such as those outside a crop window, drop theirs.
+    pub fn merge_splats(&mut self) {
+        let samples: u64 = self.pixels.iter().map(|pixel| pixel.samples as u64).sum();
+        if samples == 0 {
+            return;
+        }
+        let scale = (self.width * self.height) as Real / samples as Real;
+        for pixel in self.pixels.iter_mut() {
+            pixel.sum += (pixel.weight_sum * scale) * pixel.splat;
+            pixel.splat = Vec3::new(0.0, 0.0, 0.0);
+        }
+    }
+
+    // Copy of a rectangle of the film, starting at x0, y0.
+    pub fn crop(&self, x0: u32, y0: u32, width: u32, height: u32) -> Film {
+        let pixels = (y0..y0 + height)
+            .flat_map(|y| (x0..x0 + width).map(move |x| (x, y)))
+            .map(|(x, y)| *self.pixel(x, y))
+            .collect();
+        Film { width, height, pixels }
+    }
+
+    // Copies another film over this one, with its top left corner at x0, y0.
+    pub fn paste(&mut self, x0: u32, y0: u32, film: &Film) {
+        for y in 0..film.height {
+            for x in 0..film.width {
+                *self.pixel_mut(x0 + x, y0 + y) = *film.pixel(x, y);
+            }
+        }
+    }
+
+    // Material keys are only meaningful within one run, so pixels restored from a checkpoint written by
+    // another keep their object ids but may get different material ids.
+    pub fn write_pixels<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        for pixel in self.pixels.iter() {
+            pixel.write_to(writer)?;
+        }
+        Ok(())
+    }
+
+    // The size may come from an untrusted source: the pixels are collected as they arrive rather than
+    // allocated up front, so a bogus size runs out of data instead of memory.
+    pub fn read_pixels<R: Read>(width: u32, height: u32, reader: &mut R) -> io::Result<Film> {
+        let count = width.checked_mul(height).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "film is too large"))?;
+        let pixels = (0..count)
+            .map(|_| FilmPixel::read_from(reader))
+            .collect::<io::Result<Vec<FilmPixel>>>()?;
+        Ok(Film { width, height, pixels })
+    }
+
+    // Resolves the accumulated samples into an image, using one of the pixel getters.
+    pub fn to_image(&self, value: fn(&FilmPixel) -> Vec3) -> Image {
+        Image {
+            width: self.width as usize,
+            height: self.height as usize,
+            pixels: self.pixels.iter().map(value).collect(),
+        }
+    }
+
+    // Debug view of where samples went: black for none, through blue and green, to red for the most.
+    pub fn write_sample_heatmap<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let max_samples = self.pixels.iter().map(|pixel| pixel.samples).max().unwrap_or(0).max(1);
+        let mut buffer = BufWriter::new(File::create(path)?);
+        write!(&mut buffer, "P3\n{} {}\n255\n", self.width, self.height)?;
+        for pixel in self.pixels.iter() {
+            let t = pixel.samples as Real / max_samples as Real;
+            let color = if t < 0.5 {
+                Vec3::new(0.0, 2.0 * t, 1.0 - 2.0 * t)
+            } else {
+                Vec3::new(2.0 * t - 1.0, 2.0 - 2.0 * t, 0.0)
+            };
+            let color = if pixel.samples == 0 { Vec3::new(0.0, 0.0, 0.0) } else { color };
+            writeln!(&mut buffer, "{} {} {}", (255.999 * color.x()) as u8, (255.999 * color.y()) as u8, (255.999 * color.z()) as u8)?;
+        }
+        buffer.flush()
+    }
+}
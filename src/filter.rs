@@ -0,0 +1,159 @@
+use crate::rtweekend::{Real, PI};
+use crate::sampling::Distribution2D;
+
+// Pixel reconstruction filter, evaluated at an offset from the pixel centre in pixel units.
+pub trait Filter {
+    fn radius(&self) -> Real;
+    fn evaluate(&self, x: Real, y: Real) -> Real;
+}
+
+pub struct BoxFilter {
+    pub radius: Real,
+}
+
+impl BoxFilter {
+    pub fn new(radius: Real) -> Self { BoxFilter{radius} }
+}
+
+impl Default for BoxFilter {
+    fn default() -> Self {
+        BoxFilter::new(0.5)
+    }
+}
+
+impl Filter for BoxFilter {
+    fn radius(&self) -> Real { self.radius }
+
+    fn evaluate(&self, x: Real, y: Real) -> Real {
+        if x.abs() <= self.radius && y.abs() <= self.radius { 1.0 } else { 0.0 }
+    }
+}
+
+pub struct TentFilter {
+    pub radius: Real,
+}
+
+impl TentFilter {
+    pub fn new(radius: Real) -> Self { TentFilter{radius} }
+}
+
+impl Filter for TentFilter {
+    fn radius(&self) -> Real { self.radius }
+
+    fn evaluate(&self, x: Real, y: Real) -> Real {
+        (self.radius - x.abs()).max(0.0) * (self.radius - y.abs()).max(0.0)
+    }
+}
+
+pub struct GaussianFilter {
+    pub radius: Real,
+    pub sigma: Real,
+}
+
+impl GaussianFilter {
+    pub fn new(radius: Real, sigma: Real) -> Self { GaussianFilter{radius, sigma} }
+
+    // shifted down so it reaches zero at the radius
+    fn gaussian(&self, x: Real) -> Real {
+        let g = |x: Real| (-x * x / (2.0 * self.sigma * self.sigma)).exp();
+        (g(x) - g(self.radius)).max(0.0)
+    }
+}
+
+impl Filter for GaussianFilter {
+    fn radius(&self) -> Real { self.radius }
+
+    fn evaluate(&self, x: Real, y: Real) -> Real {
+        self.gaussian(x) * self.gaussian(y)
+    }
+}
+
+// Mitchell-Netravali cubic, b = c = 1/3 being the recommended trade-off between blur and ringing.
+pub struct MitchellFilter {
+    pub radius: Real,
+    pub b: Real,
+    pub c: Real,
+}
+
+impl MitchellFilter {
+    pub fn new(radius: Real, b: Real, c: Real) -> Self { MitchellFilter{radius, b, c} }
+
+    fn mitchell_1d(&self, x: Real) -> Real {
+        let (b, c) = (self.b, self.c);
+        let x = x.abs();
+        if x <= 1.0 {
+            ((12.0 - 9.0 * b - 6.0 * c) * x * x * x + (-18.0 + 12.0 * b + 6.0 * c) * x * x + (6.0 - 2.0 * b)) / 6.0
+        } else if x <= 2.0 {
+            ((-b - 6.0 * c) * x * x * x + (6.0 * b + 30.0 * c) * x * x + (-12.0 * b - 48.0 * c) * x + (8.0 * b + 24.0 * c)) / 6.0
+        } else {
+            0.0
+        }
+    }
+}
+
+impl Filter for MitchellFilter {
+    fn radius(&self) -> Real { self.radius }
+
+    fn evaluate(&self, x: Real, y: Real) -> Real {
+        self.mitchell_1d(2.0 * x / self.radius) * self.mitchell_1d(2.0 * y / self.radius)
+    }
+}
+
+// Sinc windowed by a wider sinc that reaches zero at the radius.
+pub struct LanczosFilter {
+    pub radius: Real,
+}
+
+impl LanczosFilter {
+    pub fn new(radius: Real) -> Self { LanczosFilter{radius} }
+
+    fn lanczos(&self, x: Real) -> Real {
+        let sinc = |x: Real| if x.abs() < 1E-5 { 1.0 } else { (PI * x).sin() / (PI * x) };
+        if x.abs() > self.radius { 0.0 } else { sinc(x) * sinc(x / self.radius) }
+    }
+}
+
+impl Filter for LanczosFilter {
+    fn radius(&self) -> Real { self.radius }
+
+    fn evaluate(&self, x: Real, y: Real) -> Real {
+        self.lanczos(x) * self.lanczos(y)
+    }
+}
+
+// Draws pixel offsets in proportion to the filter's magnitude (filter importance sampling). The
+// returned weight is the filter value over the density: constant for positive filters, and
+// negative where filters like Mitchell and Lanczos have negative lobes.
+pub struct FilterSampler {
+    radius: Real,
+    distribution: Distribution2D,
+}
+
+impl FilterSampler {
+    pub fn new(filter: &dyn Filter) -> Self {
+        let radius = filter.radius();
+        let resolution = ((64.0 * radius).ceil() as usize).max(2);
+        let mut func = Vec::with_capacity(resolution * resolution);
+        for y in 0..resolution {
+            for x in 0..resolution {
+                let px = -radius + 2.0 * radius * (x as Real + 0.5) / resolution as Real;
+                let py = -radius + 2.0 * radius * (y as Real + 0.5) / resolution as Real;
+                func.push(filter.evaluate(px, py));
+            }
+        }
+        FilterSampler { radius, distribution: Distribution2D::new(&func, resolution, resolution) }
+    }
+
+    // Returns the offset from the pixel centre and the sample weight.
+    pub fn sample(&self, filter: &dyn Filter, u: (Real, Real)) -> (Real, Real, Real) {
+        let ((su, sv), pdf) = self.distribution.sample_continuous(u);
+        let x = -self.radius + 2.0 * self.radius * su;
+        let y = -self.radius + 2.0 * self.radius * sv;
+        // the tabulated density is over the unit square, rescale it to the filter's extent
+        let pdf = pdf / (4.0 * self.radius * self.radius);
+        if pdf == 0.0 {
+            return (x, y, 0.0);
+        }
+        (x, y, filter.evaluate(x, y) / pdf)
+    }
+}
@@ -1,36 +1,66 @@
 use crate::vec3::{Vec3, dot};
-use crate::ray::Ray;
+use crate::ray::{Ray, offset_ray_origin};
 use crate::interval::Interval;
 use crate::material::{Lambertian, Material};
 use std::rc::Rc;
+use crate::rtweekend::Real;
 
 #[derive(Clone)]
 pub struct HitRecord {
     pub p: Vec3,
+    // bound on the absolute rounding error in each coordinate of p
+    pub p_error: Vec3,
+    // geometric normal, always facing against the incoming ray
     pub normal: Vec3,
-    pub t: f64,
+    // normal used for shading, perturbed by normal and bump maps
+    pub shading_normal: Vec3,
+    pub dpdu: Vec3,
+    pub dpdv: Vec3,
+    pub u: Real,
+    pub v: Real,
+    pub t: Real,
     pub front_face : bool,
     pub material: Rc<dyn Material>,
+    // index of the object in the HittableList that was hit, counting the members of groups one by one
+    pub object_id: u32,
+    // time of the ray that made the hit, carried over to the rays spawned from it
+    pub time: Real,
 }
 
 impl HitRecord {
-    pub fn new(p: Vec3, normal: Vec3, t: f64, front_face: bool, material: Rc<dyn Material>) -> Self {
+    pub fn new(p: Vec3, normal: Vec3, t: Real, front_face: bool, material: Rc<dyn Material>) -> Self {
         HitRecord{
-            p: p,
-            normal: normal,
-            t: t,
-            front_face: front_face,
-            material: material,
+            p,
+            p_error: Vec3::new(0.0, 0.0, 0.0),
+            normal,
+            shading_normal: normal,
+            dpdu: Vec3::new(0.0, 0.0, 0.0),
+            dpdv: Vec3::new(0.0, 0.0, 0.0),
+            u: 0.0,
+            v: 0.0,
+            t,
+            front_face,
+            material,
+            object_id: 0,
+            time: 0.0,
         }
     }
 
     pub fn initialize() -> Self {
         HitRecord { 
             p: Vec3::new(0.0, 0.0, 0.0),
+            p_error: Vec3::new(0.0, 0.0, 0.0),
             normal: Vec3::new(0.0, 0.0, 0.0), 
+            shading_normal: Vec3::new(0.0, 0.0, 0.0),
+            dpdu: Vec3::new(0.0, 0.0, 0.0),
+            dpdv: Vec3::new(0.0, 0.0, 0.0),
+            u: 0.0,
+            v: 0.0,
             t: 1E8, 
             front_face: false,
             material: Rc::new(Lambertian::new(Vec3::new(0.0, 0.0, 0.0))),
+            object_id: 0,
+            time: 0.0,
         }
     }
 
@@ -41,11 +71,32 @@ impl HitRecord {
         } else {
             self.normal = -outward_normal;
         }
+        self.shading_normal = self.normal;
+    }
+
+    // Replaces the shading normal, keeping it in the same hemisphere as the geometric normal.
+    pub fn set_shading_normal(&mut self, n: Vec3) {
+        if dot(n, self.normal) < 0.0 {
+            self.shading_normal = -n;
+        } else {
+            self.shading_normal = n;
+        }
+    }
+
+    // Spawns a ray leaving the surface, from just outside the error bounds of p on the side it travels into.
+    pub fn spawn_ray(&self, direction: Vec3) -> Ray {
+        Ray::with_time(offset_ray_origin(self.p, self.p_error, self.normal, direction), direction, self.time)
     }
 }
 
 pub trait Hittable {
     fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool;
+
+    // Object ids the object takes up. Groups number their members from 0 and set object_id to the
+    // one that was hit; HittableList then offsets it by the ids of the objects before the group.
+    fn object_count(&self) -> u32 {
+        1
+    }
 }
 
 pub struct HittableList {
@@ -64,17 +115,22 @@ impl HittableList {
     pub fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
         let mut temp_rec: HitRecord = HitRecord::initialize();
         let mut hit_anything: bool = false;
-        let mut closest_so_far: f64 = ray_t.max;
+        let mut closest_so_far: Real = ray_t.max;
 
+        let mut first_id = 0;
         for object in self.objects.iter() {
+            temp_rec.object_id = 0;
             if object.hit(r, Interval::new(ray_t.min, closest_so_far), &mut temp_rec) {
+                temp_rec.object_id += first_id;
+                temp_rec.time = r.time();
                 hit_anything = true;
                 closest_so_far = temp_rec.t;
                 *rec = temp_rec.clone();
             }
+            first_id += object.object_count();
         }
 
-        return hit_anything;
+        hit_anything
 
     }
-}
\ No newline at end of file
+}
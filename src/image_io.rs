@@ -0,0 +1,510 @@
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Error, ErrorKind, Read, Write};
+use std::path::Path;
+use crate::color::{encode_color, srgb_to_linear, write_color};
+use crate::colorspace::ColorSpace;
+use crate::vec3::Vec3;
+use crate::rtweekend::Real;
+
+// A floating point RGB image, stored row by row starting from the top left pixel.
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<Vec3>,
+}
+
+impl Image {
+    pub fn new(width: usize, height: usize) -> Self {
+        Image { width, height, pixels: vec![Vec3::new(0.0, 0.0, 0.0); width * height] }
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> Vec3 {
+        self.pixels[y * self.width + x]
+    }
+
+    pub fn set_pixel(&mut self, x: usize, y: usize, color: Vec3) {
+        self.pixels[y * self.width + x] = color;
+    }
+}
+
+// Loads an image, picking the decoder from the file extension. 8 and 16 bit formats are mapped to [0,1],
+// high dynamic range formats are returned as stored.
+pub fn load_image<P: AsRef<Path>>(path: P) -> io::Result<Image> {
+    let path = path.as_ref();
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_ascii_lowercase();
+    match extension.as_str() {
+        "ppm" => read_ppm(path),
+        "png" => read_png(path),
+        "hdr" => read_hdr(path),
+        "pfm" => read_pfm(path),
+        _ => Err(Error::new(ErrorKind::InvalidInput, format!("unsupported image format: {}", path.display()))),
+    }
+}
+
+// Loads an image holding colours, ready for use in the given working space. 8 and 16 bit formats are
+// decoded from the sRGB transfer curve; every format is taken to use sRGB primaries. Data such as
+// normal maps and bump heights should be loaded with load_image instead.
+pub fn load_color_image<P: AsRef<Path>>(path: P, working_space: ColorSpace) -> io::Result<Image> {
+    let mut image = load_image(&path)?;
+    if !is_float_format(&path) {
+        for pixel in image.pixels.iter_mut() {
+            *pixel = Vec3::new(srgb_to_linear(pixel.x()), srgb_to_linear(pixel.y()), srgb_to_linear(pixel.z()));
+        }
+    }
+    Ok(ColorSpace::LinearSrgb.convert_image(&image, working_space))
+}
+
+// Whether the file extension is one of the formats that store linear floating point values.
+pub fn is_float_format<P: AsRef<Path>>(path: P) -> bool {
+    let extension = path.as_ref().extension().and_then(|e| e.to_str()).unwrap_or("").to_ascii_lowercase();
+    matches!(extension.as_str(), "hdr" | "pfm" | "exr")
+}
+
+// Writes linear pixels in the given colour space, picking the encoder from the file extension.
+// PPM and PNG are encoded with the sRGB transfer curve, HDR, PFM and EXR keep the values as they are.
+pub fn write_image<P: AsRef<Path>>(image: &Image, space: ColorSpace, path: P) -> io::Result<()> {
+    let path = path.as_ref();
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_ascii_lowercase();
+    match extension.as_str() {
+        "ppm" => write_ppm(image, space, path),
+        "png" => write_png(image, space, path),
+        "hdr" => write_hdr(image, space, path),
+        "pfm" => write_pfm(image, path),
+        "exr" => {
+            let channel = |c: usize| image.pixels.iter().map(|p| p[c] as f32).collect();
+            let channels = [(String::from("R"), channel(0)), (String::from("G"), channel(1)), (String::from("B"), channel(2))];
+            write_exr(image.width, image.height, &channels, space, path)
+        }
+        _ => Err(Error::new(ErrorKind::InvalidInput, format!("unsupported image format: {}", path.display()))),
+    }
+}
+
+// Writes the image as an ascii PPM, gamma corrected the same way as the renders. The format has
+// no colour metadata, so the space is only named in a header comment.
+pub fn write_ppm<P: AsRef<Path>>(image: &Image, space: ColorSpace, path: P) -> io::Result<()> {
+    let mut buffer = BufWriter::new(File::create(path)?);
+    write!(&mut buffer, "P3\n# colorspace: {}\n{} {}\n255\n", space.name(), image.width, image.height)?;
+    for pixel in image.pixels.iter() {
+        write!(&mut buffer, "{}", write_color(pixel, 1))?;
+    }
+    buffer.flush()
+}
+
+// Writes an 8 bit PNG encoded with the sRGB curve, tagged with the primaries and white of the space.
+pub fn write_png<P: AsRef<Path>>(image: &Image, space: ColorSpace, path: P) -> io::Result<()> {
+    let mut encoder = png::Encoder::new(BufWriter::new(File::create(path)?), image.width as u32, image.height as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    let [red, green, blue, white] = space.chromaticities().map(|(x, y)| (x as f32, y as f32));
+    encoder.set_source_chromaticities(png::SourceChromaticities::new(white, red, green, blue));
+    encoder.set_source_gamma(png::ScaledFloat::new(1.0 / 2.2));
+    let mut writer = encoder.write_header().map_err(|e| Error::other(e.to_string()))?;
+
+    let data: Vec<u8> = image.pixels.iter().flat_map(|pixel| encode_color(pixel, 1)).collect();
+    writer.write_image_data(&data).map_err(|e| Error::other(e.to_string()))
+}
+
+fn invalid_data(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message.to_string())
+}
+
+// Reads both the ascii (P3) and binary (P6) flavours of PPM.
+pub fn read_ppm<P: AsRef<Path>>(path: P) -> io::Result<Image> {
+    let mut bytes = Vec::new();
+    BufReader::new(File::open(path)?).read_to_end(&mut bytes)?;
+
+    let mut pos = 0;
+    let mut next_token = |bytes: &[u8]| -> io::Result<String> {
+        loop {
+            while pos < bytes.len() && bytes[pos].is_ascii_whitespace() {
+                pos += 1;
+            }
+            if pos < bytes.len() && bytes[pos] == b'#' {
+                while pos < bytes.len() && bytes[pos] != b'\n' {
+                    pos += 1;
+                }
+            } else {
+                break;
+            }
+        }
+        let start = pos;
+        while pos < bytes.len() && !bytes[pos].is_ascii_whitespace() {
+            pos += 1;
+        }
+        if start == pos {
+            return Err(invalid_data("unexpected end of ppm file"));
+        }
+        Ok(String::from_utf8_lossy(&bytes[start..pos]).into_owned())
+    };
+    let parse = |token: String| -> io::Result<usize> {
+        token.parse::<usize>().map_err(|_| invalid_data("malformed ppm header"))
+    };
+
+    let magic = next_token(&bytes)?;
+    let width = parse(next_token(&bytes)?)?;
+    let height = parse(next_token(&bytes)?)?;
+    let max_value = parse(next_token(&bytes)?)?.max(1) as Real;
+
+    let mut image = Image::new(width, height);
+    match magic.as_str() {
+        "P3" => {
+            for i in 0..width * height {
+                let r = parse(next_token(&bytes)?)? as Real;
+                let g = parse(next_token(&bytes)?)? as Real;
+                let b = parse(next_token(&bytes)?)? as Real;
+                image.pixels[i] = Vec3::new(r, g, b) / max_value;
+            }
+        }
+        "P6" => {
+            // a single whitespace byte separates the header from the raster
+            let data = &bytes[(pos + 1).min(bytes.len())..];
+            let wide = max_value > 255.0;
+            let stride = if wide { 6 } else { 3 };
+            if data.len() < width * height * stride {
+                return Err(invalid_data("truncated ppm raster"));
+            }
+            for i in 0..width * height {
+                let texel = &data[i * stride..(i + 1) * stride];
+                let channel = |c: usize| -> Real {
+                    if wide {
+                        u16::from_be_bytes([texel[2 * c], texel[2 * c + 1]]) as Real
+                    } else {
+                        texel[c] as Real
+                    }
+                };
+                image.pixels[i] = Vec3::new(channel(0), channel(1), channel(2)) / max_value;
+            }
+        }
+        _ => return Err(invalid_data("not a ppm file")),
+    }
+    Ok(image)
+}
+
+pub fn read_png<P: AsRef<Path>>(path: P) -> io::Result<Image> {
+    let mut decoder = png::Decoder::new(BufReader::new(File::open(path)?));
+    decoder.set_transformations(png::Transformations::EXPAND);
+    let mut reader = decoder.read_info().map_err(|e| invalid_data(&e.to_string()))?;
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buffer).map_err(|e| invalid_data(&e.to_string()))?;
+
+    let channels = info.color_type.samples();
+    let wide = info.bit_depth == png::BitDepth::Sixteen;
+    let max_value = if wide { 65535.0 } else { 255.0 };
+    let sample = |index: usize| -> Real {
+        if wide {
+            u16::from_be_bytes([buffer[2 * index], buffer[2 * index + 1]]) as Real / max_value
+        } else {
+            buffer[index] as Real / max_value
+        }
+    };
+
+    let width = info.width as usize;
+    let height = info.height as usize;
+    let mut image = Image::new(width, height);
+    for i in 0..width * height {
+        let base = i * channels;
+        image.pixels[i] = match info.color_type {
+            png::ColorType::Grayscale | png::ColorType::GrayscaleAlpha => {
+                let l = sample(base);
+                Vec3::new(l, l, l)
+            }
+            _ => Vec3::new(sample(base), sample(base + 1), sample(base + 2)),
+        };
+    }
+    Ok(image)
+}
+
+// Reads a Radiance RGBE (.hdr) image, with or without run length encoded scanlines.
+pub fn read_hdr<P: AsRef<Path>>(path: P) -> io::Result<Image> {
+    let mut bytes = Vec::new();
+    BufReader::new(File::open(path)?).read_to_end(&mut bytes)?;
+
+    let mut pos = 0;
+    let mut next_line = |bytes: &[u8]| -> io::Result<String> {
+        let start = pos;
+        while pos < bytes.len() && bytes[pos] != b'\n' {
+            pos += 1;
+        }
+        if pos >= bytes.len() {
+            return Err(invalid_data("unexpected end of hdr header"));
+        }
+        pos += 1;
+        Ok(String::from_utf8_lossy(&bytes[start..pos - 1]).trim().to_string())
+    };
+
+    let magic = next_line(&bytes)?;
+    if !magic.starts_with("#?") {
+        return Err(invalid_data("not a radiance hdr file"));
+    }
+    loop {
+        let line = next_line(&bytes)?;
+        if line.is_empty() {
+            break;
+        }
+        if let Some(format) = line.strip_prefix("FORMAT=") {
+            if format != "32-bit_rle_rgbe" {
+                return Err(invalid_data("only rgbe hdr files are supported"));
+            }
+        }
+    }
+
+    let resolution = next_line(&bytes)?;
+    let fields: Vec<&str> = resolution.split_whitespace().collect();
+    if fields.len() != 4 || fields[0] != "-Y" || fields[2] != "+X" {
+        return Err(invalid_data("unsupported hdr orientation"));
+    }
+    let height: usize = fields[1].parse().map_err(|_| invalid_data("malformed hdr resolution"))?;
+    let width: usize = fields[3].parse().map_err(|_| invalid_data("malformed hdr resolution"))?;
+
+    let mut image = Image::new(width, height);
+    let mut scanline = vec![0u8; width * 4];
+    for y in 0..height {
+        let rle = (8..0x8000).contains(&width)
+            && bytes.len() >= pos + 4
+            && bytes[pos] == 2
+            && bytes[pos + 1] == 2
+            && bytes[pos + 2] & 0x80 == 0;
+        if rle {
+            pos += 4;
+            // each of the four channels is run length encoded separately
+            for channel in 0..4 {
+                let mut x = 0;
+                while x < width {
+                    let count = *bytes.get(pos).ok_or_else(|| invalid_data("truncated hdr scanline"))? as usize;
+                    pos += 1;
+                    if count > 128 {
+                        let run = count - 128;
+                        let value = *bytes.get(pos).ok_or_else(|| invalid_data("truncated hdr scanline"))?;
+                        pos += 1;
+                        if x + run > width {
+                            return Err(invalid_data("bad hdr run length"));
+                        }
+                        for i in 0..run {
+                            scanline[(x + i) * 4 + channel] = value;
+                        }
+                        x += run;
+                    } else {
+                        if count == 0 || x + count > width || pos + count > bytes.len() {
+                            return Err(invalid_data("bad hdr run length"));
+                        }
+                        for i in 0..count {
+                            scanline[(x + i) * 4 + channel] = bytes[pos + i];
+                        }
+                        pos += count;
+                        x += count;
+                    }
+                }
+            }
+        } else {
+            if pos + width * 4 > bytes.len() {
+                return Err(invalid_data("truncated hdr raster"));
+            }
+            scanline.copy_from_slice(&bytes[pos..pos + width * 4]);
+            pos += width * 4;
+        }
+
+        for x in 0..width {
+            let rgbe = &scanline[x * 4..x * 4 + 4];
+            let color = if rgbe[3] == 0 {
+                Vec3::new(0.0, 0.0, 0.0)
+            } else {
+                let f = Real::powi(2.0, rgbe[3] as i32 - 136);
+                Vec3::new(rgbe[0] as Real * f, rgbe[1] as Real * f, rgbe[2] as Real * f)
+            };
+            image.set_pixel(x, y, color);
+        }
+    }
+    Ok(image)
+}
+
+// Packs a linear colour into Radiance's shared exponent format. Negative values are clamped to 0.
+fn encode_rgbe(color: Vec3) -> [u8; 4] {
+    let max = color.x().max(color.y()).max(color.z());
+    if max < 1e-32 {
+        return [0, 0, 0, 0];
+    }
+    // max = m * 2^exponent with m in [0.5, 1)
+    let exponent = max.log2().floor() as i32 + 1;
+    let scale = 256.0 / Real::powi(2.0, exponent);
+    let byte = |c: Real| (c.max(0.0) * scale).min(255.0) as u8;
+    [byte(color.x()), byte(color.y()), byte(color.z()), (exponent + 128).clamp(0, 255) as u8]
+}
+
+// Writes a Radiance RGBE (.hdr) image, tagged with the primaries and white of the space. Scanlines
+// are run length encoded when the width allows it, using literal runs only.
+pub fn write_hdr<P: AsRef<Path>>(image: &Image, space: ColorSpace, path: P) -> io::Result<()> {
+    let mut buffer = BufWriter::new(File::create(path)?);
+    let primaries: Vec<String> = space.chromaticities().iter().map(|(x, y)| format!("{} {}", x, y)).collect();
+    write!(&mut buffer, "#?RADIANCE\nFORMAT=32-bit_rle_rgbe\nPRIMARIES= {}\n\n", primaries.join(" "))?;
+    writeln!(&mut buffer, "-Y {} +X {}", image.height, image.width)?;
+
+    let rle = (8..0x8000).contains(&image.width);
+    let mut scanline = vec![0u8; image.width * 4];
+    for y in 0..image.height {
+        for x in 0..image.width {
+            scanline[x * 4..x * 4 + 4].copy_from_slice(&encode_rgbe(image.pixel(x, y)));
+        }
+        if !rle {
+            buffer.write_all(&scanline)?;
+            continue;
+        }
+        // a flat scanline could start with bytes that look like the run length marker
+        buffer.write_all(&[2, 2, (image.width >> 8) as u8, (image.width & 0xff) as u8])?;
+        for channel in 0..4 {
+            let values: Vec<u8> = (0..image.width).map(|x| scanline[x * 4 + channel]).collect();
+            for chunk in values.chunks(128) {
+                buffer.write_all(&[chunk.len() as u8])?;
+                buffer.write_all(chunk)?;
+            }
+        }
+    }
+    buffer.flush()
+}
+
+// Reads a portable float map, either colour (PF) or greyscale (Pf).
+pub fn read_pfm<P: AsRef<Path>>(path: P) -> io::Result<Image> {
+    let mut bytes = Vec::new();
+    BufReader::new(File::open(path)?).read_to_end(&mut bytes)?;
+
+    let mut pos = 0;
+    let mut next_token = |bytes: &[u8]| -> io::Result<String> {
+        while pos < bytes.len() && bytes[pos].is_ascii_whitespace() {
+            pos += 1;
+        }
+        let start = pos;
+        while pos < bytes.len() && !bytes[pos].is_ascii_whitespace() {
+            pos += 1;
+        }
+        if start == pos {
+            return Err(invalid_data("unexpected end of pfm header"));
+        }
+        Ok(String::from_utf8_lossy(&bytes[start..pos]).into_owned())
+    };
+
+    let channels = match next_token(&bytes)?.as_str() {
+        "PF" => 3,
+        "Pf" => 1,
+        _ => return Err(invalid_data("not a pfm file")),
+    };
+    let width: usize = next_token(&bytes)?.parse().map_err(|_| invalid_data("malformed pfm header"))?;
+    let height: usize = next_token(&bytes)?.parse().map_err(|_| invalid_data("malformed pfm header"))?;
+    let scale: Real = next_token(&bytes)?.parse().map_err(|_| invalid_data("malformed pfm header"))?;
+    let little_endian = scale < 0.0;
+
+    let data = &bytes[(pos + 1).min(bytes.len())..];
+    if data.len() < width * height * channels * 4 {
+        return Err(invalid_data("truncated pfm raster"));
+    }
+    let sample = |index: usize| -> Real {
+        let raw = [data[index * 4], data[index * 4 + 1], data[index * 4 + 2], data[index * 4 + 3]];
+        if little_endian { f32::from_le_bytes(raw) as Real } else { f32::from_be_bytes(raw) as Real }
+    };
+
+    // rows are stored bottom to top
+    let mut image = Image::new(width, height);
+    for row in 0..height {
+        for x in 0..width {
+            let base = (row * width + x) * channels;
+            let color = if channels == 3 {
+                Vec3::new(sample(base), sample(base + 1), sample(base + 2))
+            } else {
+                Vec3::new(sample(base), sample(base), sample(base))
+            };
+            image.set_pixel(x, height - 1 - row, color);
+        }
+    }
+    Ok(image)
+}
+
+// Writes the image as a little endian colour portable float map, without any tone mapping.
+pub fn write_pfm<P: AsRef<Path>>(image: &Image, path: P) -> io::Result<()> {
+    let mut buffer = BufWriter::new(File::create(path)?);
+    write!(&mut buffer, "PF\n{} {}\n-1.0\n", image.width, image.height)?;
+    // rows are stored bottom to top
+    for y in (0..image.height).rev() {
+        for x in 0..image.width {
+            let pixel = image.pixel(x, y);
+            for value in [pixel.x(), pixel.y(), pixel.z()] {
+                buffer.write_all(&(value as f32).to_le_bytes())?;
+            }
+        }
+    }
+    buffer.flush()
+}
+
+// Writes named float channels as an uncompressed scanline OpenEXR file. Layers are expressed with
+// the usual "layer.channel" names, so a beauty image and its passes can share one file. The colour
+// space is recorded in the chromaticities attribute.
+pub fn write_exr<P: AsRef<Path>>(width: usize, height: usize, channels: &[(String, Vec<f32>)], space: ColorSpace, path: P) -> io::Result<()> {
+    if channels.iter().any(|(_, data)| data.len() != width * height) {
+        return Err(Error::new(ErrorKind::InvalidInput, "exr channel size doesn't match the image"));
+    }
+    // readers expect the channel list sorted by name, and the pixel data in the same order
+    let mut channels: Vec<&(String, Vec<f32>)> = channels.iter().collect();
+    channels.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let mut header = Vec::new();
+    let mut attribute = |name: &str, kind: &str, value: &[u8]| {
+        header.extend_from_slice(name.as_bytes());
+        header.push(0);
+        header.extend_from_slice(kind.as_bytes());
+        header.push(0);
+        header.extend_from_slice(&(value.len() as i32).to_le_bytes());
+        header.extend_from_slice(value);
+    };
+
+    let mut chlist = Vec::new();
+    for (name, _) in channels.iter() {
+        chlist.extend_from_slice(name.as_bytes());
+        chlist.push(0);
+        // FLOAT pixels, not perceptually linear, reserved, x and y sampling
+        chlist.extend_from_slice(&2i32.to_le_bytes());
+        chlist.extend_from_slice(&[0, 0, 0, 0]);
+        chlist.extend_from_slice(&1i32.to_le_bytes());
+        chlist.extend_from_slice(&1i32.to_le_bytes());
+    }
+    chlist.push(0);
+    let mut window = Vec::new();
+    for value in [0, 0, width as i32 - 1, height as i32 - 1] {
+        window.extend_from_slice(&value.to_le_bytes());
+    }
+
+    let mut chromaticities = Vec::new();
+    for (x, y) in space.chromaticities() {
+        chromaticities.extend_from_slice(&(x as f32).to_le_bytes());
+        chromaticities.extend_from_slice(&(y as f32).to_le_bytes());
+    }
+
+    attribute("channels", "chlist", &chlist);
+    attribute("chromaticities", "chromaticities", &chromaticities);
+    attribute("compression", "compression", &[0]);
+    attribute("dataWindow", "box2i", &window);
+    attribute("displayWindow", "box2i", &window);
+    attribute("lineOrder", "lineOrder", &[0]);
+    attribute("pixelAspectRatio", "float", &1.0f32.to_le_bytes());
+    attribute("screenWindowCenter", "v2f", &[0; 8]);
+    attribute("screenWindowWidth", "float", &1.0f32.to_le_bytes());
+    header.push(0);
+
+    let mut buffer = BufWriter::new(File::create(path)?);
+    // magic number, then version 2 with no flags set
+    buffer.write_all(&[0x76, 0x2f, 0x31, 0x01, 2, 0, 0, 0])?;
+    buffer.write_all(&header)?;
+
+    // offset table pointing at each scanline, which holds its y, byte count and then each channel in turn
+    let line_size = channels.len() * width * 4;
+    let first_line = 8 + header.len() + height * 8;
+    for y in 0..height {
+        buffer.write_all(&((first_line + y * (line_size + 8)) as u64).to_le_bytes())?;
+    }
+    for y in 0..height {
+        buffer.write_all(&(y as i32).to_le_bytes())?;
+        buffer.write_all(&(line_size as i32).to_le_bytes())?;
+        for (_, data) in channels.iter() {
+            for value in &data[y * width..(y + 1) * width] {
+                buffer.write_all(&value.to_le_bytes())?;
+            }
+        }
+    }
+    buffer.flush()
+}
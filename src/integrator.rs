@@ -0,0 +1,321 @@
+use std::rc::Rc;
+use crate::camera::View;
+use crate::film::{Features, Splat};
+use crate::hittable::HitRecord;
+use crate::interval::Interval;
+use crate::material::{Lobe, Material};
+use crate::ray::Ray;
+use crate::rtweekend::{Real, INFINITY};
+use crate::sampler::Sampler;
+use crate::sampling::{power_heuristic, sample_uniform_sphere};
+use crate::scene::Scene;
+use crate::vec3::{Vec3, dot, unit_vector};
+
+// Rendering algorithm: works out what a camera ray sees. features records what the camera ray hit,
+// along with the light that arrived after a single bounce and everything else, for the AOVs and the
+// denoiser.
+pub trait Integrator {
+    fn radiance(&self, r: Ray, scene: &Scene, sampler: &mut dyn Sampler, features: &mut Features) -> Vec3;
+
+    // Same as radiance, for cameras seen through view, where light the integrator finds reaching other
+    // pixels than the camera ray's is pushed to splats instead.
+    fn radiance_with_splats(&self, r: Ray, scene: &Scene, sampler: &mut dyn Sampler, features: &mut Features, _view: &View, _splats: &mut Vec<Splat>) -> Vec3 {
+        self.radiance(r, scene, sampler, features)
+    }
+}
+
+// How paths are ended and how much light a single sample may carry.
+#[derive(Clone, Copy, Debug)]
+pub struct PathSettings {
+    // bounces of each kind a path may take, None leaves that kind limited only by max_depth
+    pub max_diffuse_depth: Option<u32>,
+    pub max_specular_depth: Option<u32>,
+    pub max_transmission_depth: Option<u32>,
+    // bounces before Russian roulette starts ending paths that carry little light
+    pub roulette_depth: u32,
+    // radiance of each sample is scaled down to at most this in every channel, to suppress fireflies
+    // at the cost of some energy; None disables it
+    pub max_radiance: Option<Real>,
+}
+
+impl Default for PathSettings {
+    fn default() -> Self {
+        PathSettings {
+            max_diffuse_depth: None,
+            max_specular_depth: None,
+            max_transmission_depth: None,
+            roulette_depth: 3,
+            max_radiance: None,
+        }
+    }
+}
+
+impl PathSettings {
+    fn max_lobe_depth(&self, lobe: Lobe) -> Option<u32> {
+        match lobe {
+            Lobe::Diffuse => self.max_diffuse_depth,
+            Lobe::Specular => self.max_specular_depth,
+            Lobe::Transmission => self.max_transmission_depth,
+        }
+    }
+}
+
+// Path tracer with next event estimation: every vertex samples the lights and the background
+// directly, combined with the scattered rays that reach the background by multiple importance
+// sampling. max_depth caps the number of segments of any kind; with Russian roulette it can be set
+// high without costing much.
+#[derive(Clone, Copy, Debug)]
+pub struct PathTracer {
+    pub max_depth: u32,
+    pub path: PathSettings,
+}
+
+impl Default for PathTracer {
+    fn default() -> Self {
+        PathTracer { max_depth: 10, path: PathSettings::default() }
+    }
+}
+
+impl Integrator for PathTracer {
+    fn radiance(&self, r: Ray, scene: &Scene, sampler: &mut dyn Sampler, features: &mut Features) -> Vec3 {
+        trace_path(r, self.max_depth, &self.path, true, scene, sampler, features)
+    }
+}
+
+// Path tracer that only finds light by following the scattered rays until they leave the scene. It
+// never sees lights that rays can't hit, such as point lights, and is noisier than PathTracer, but
+// serves as a reference for it.
+#[derive(Clone, Copy, Debug)]
+pub struct NaivePathTracer {
+    pub max_depth: u32,
+    pub path: PathSettings,
+}
+
+impl Default for NaivePathTracer {
+    fn default() -> Self {
+        NaivePathTracer { max_depth: 10, path: PathSettings::default() }
+    }
+}
+
+impl Integrator for NaivePathTracer {
+    fn radiance(&self, r: Ray, scene: &Scene, sampler: &mut dyn Sampler, features: &mut Features) -> Vec3 {
+        trace_path(r, self.max_depth, &self.path, false, scene, sampler, features)
+    }
+}
+
+// Light reaching the first surface a camera ray hits straight from the lights and the background,
+// without any interreflection.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DirectLighting;
+
+impl Integrator for DirectLighting {
+    fn radiance(&self, r: Ray, scene: &Scene, sampler: &mut dyn Sampler, features: &mut Features) -> Vec3 {
+        let mut rec = HitRecord::initialize();
+        if !scene.world.hit(&r, Interval::new(0.0, INFINITY), &mut rec) {
+            let background = scene.background.value(r.direction());
+            record_miss(features, background);
+            return background;
+        }
+        let material = rec.material.clone();
+        record_hit(features, &r, &rec, &material);
+        let mut color = sample_direct(&r, &rec, &material, scene, sampler);
+
+        // the scattered ray picks up the background where it leaves the scene straight away, which
+        // is all mirrors and glass get
+        let mut scattered = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 0.0));
+        let mut attenuation = Vec3::new(0.0, 0.0, 0.0);
+        if material.scatter(&r, &mut rec, &mut attenuation, &mut scattered, sampler)
+            && !scene.world.hit(&scattered, Interval::new(0.0, INFINITY), &mut HitRecord::initialize())
+        {
+            let pdf = material.pdf(&r, &rec, unit_vector(scattered.direction()));
+            let weight = if pdf > 0.0 { power_heuristic(pdf, scene.background.pdf(scattered.direction())) } else { 1.0 };
+            color += weight * attenuation * scene.background.value(scattered.direction());
+        }
+        features.direct = color;
+        color
+    }
+}
+
+// Fraction of the hemisphere above each visible point that is open out to distance, weighted by
+// the cosine to the normal. Misses are black.
+#[derive(Clone, Copy, Debug)]
+pub struct AmbientOcclusion {
+    pub samples: u32,
+    pub distance: Real,
+}
+
+impl Default for AmbientOcclusion {
+    fn default() -> Self {
+        AmbientOcclusion { samples: 4, distance: INFINITY }
+    }
+}
+
+impl Integrator for AmbientOcclusion {
+    fn radiance(&self, r: Ray, scene: &Scene, sampler: &mut dyn Sampler, features: &mut Features) -> Vec3 {
+        let mut rec = HitRecord::initialize();
+        if !scene.world.hit(&r, Interval::new(0.0, INFINITY), &mut rec) {
+            record_miss(features, Vec3::new(0.0, 0.0, 0.0));
+            return Vec3::new(0.0, 0.0, 0.0);
+        }
+        record_hit(features, &r, &rec, &rec.material);
+
+        // directions around the normal follow the cosine, so each open one counts the same
+        let mut open = 0;
+        for _ in 0..self.samples {
+            let direction = rec.shading_normal + sample_uniform_sphere(sampler.get_2d());
+            if direction.near_zero() || dot(direction, rec.normal) <= 0.0 {
+                continue;
+            }
+            if !scene.occluded(&rec, unit_vector(direction), self.distance) {
+                open += 1;
+            }
+        }
+        let visibility = open as Real / self.samples.max(1) as Real;
+        let color = Vec3::new(visibility, visibility, visibility);
+        features.direct = color;
+        color
+    }
+}
+
+// Shows the shading normal of whatever camera rays hit, mapped from [-1, 1] to colours in [0, 1].
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Normals;
+
+impl Integrator for Normals {
+    fn radiance(&self, r: Ray, scene: &Scene, _sampler: &mut dyn Sampler, features: &mut Features) -> Vec3 {
+        let mut rec = HitRecord::initialize();
+        if !scene.world.hit(&r, Interval::new(0.0, INFINITY), &mut rec) {
+            record_miss(features, Vec3::new(0.0, 0.0, 0.0));
+            return Vec3::new(0.0, 0.0, 0.0);
+        }
+        record_hit(features, &r, &rec, &rec.material);
+        let color = 0.5 * (rec.shading_normal + Vec3::new(1.0, 1.0, 1.0));
+        features.direct = color;
+        color
+    }
+}
+
+pub(crate) fn record_hit(features: &mut Features, r: &Ray, rec: &HitRecord, material: &Rc<dyn Material>) {
+    features.albedo = material.albedo(rec);
+    features.normal = rec.shading_normal;
+    features.hit = true;
+    features.depth = rec.t * r.direction().length();
+    features.position = rec.p;
+    features.material = Rc::as_ptr(material) as *const () as usize;
+    features.object_id = rec.object_id;
+}
+
+pub(crate) fn record_miss(features: &mut Features, background: Vec3) {
+    features.albedo = Vec3::new(background.x().min(1.0), background.y().min(1.0), background.z().min(1.0));
+    features.normal = Vec3::new(0.0, 0.0, 0.0);
+}
+
+fn max_component(v: Vec3) -> Real {
+    v.x().max(v.y()).max(v.z())
+}
+
+// Light arriving at rec straight from the background and the lights, sampled towards them. The
+// background samples are weighted against the scattered ray reaching it.
+fn sample_direct(r: &Ray, rec: &HitRecord, material: &Rc<dyn Material>, scene: &Scene, sampler: &mut dyn Sampler) -> Vec3 {
+    let mut color = Vec3::new(0.0, 0.0, 0.0);
+    if let Some(light) = scene.background.sample(sampler.get_2d()) {
+        let f = material.eval(r, rec, light.direction);
+        if !f.near_zero() && !scene.occluded(rec, light.direction, INFINITY) {
+            let weight = power_heuristic(light.pdf, material.pdf(r, rec, light.direction));
+            color += (weight / light.pdf) * f * light.radiance;
+        }
+    }
+
+    // lights that rays can't hit are only reached through shadow rays
+    for light in scene.lights.iter() {
+        if let Some(sample) = light.sample_li(rec.p, sampler.get_2d()) {
+            let f = material.eval(r, rec, sample.direction);
+            if !f.near_zero() && sample.pdf > 0.0 && !scene.occluded(rec, sample.direction, sample.distance) {
+                color += (1.0 / sample.pdf) * f * sample.radiance;
+            }
+        }
+    }
+    color
+}
+
+// Follows the path of r for at most max_depth segments, with each vertex weighted by the throughput
+// of the path leading to it. With next_event each vertex also samples the lights directly.
+fn trace_path(r: Ray, max_depth: u32, settings: &PathSettings, next_event: bool, scene: &Scene, sampler: &mut dyn Sampler, features: &mut Features) -> Vec3 {
+    let mut radiance = Vec3::new(0.0, 0.0, 0.0);
+    let mut direct = Vec3::new(0.0, 0.0, 0.0);
+    let mut throughput = Vec3::new(1.0, 1.0, 1.0);
+    let mut ray = r;
+    // density with which the previous bounce picked ray, None when it can't be evaluated
+    let mut scatter_pdf: Option<Real> = None;
+    let mut lobe_depths = [0; 3];
+
+    for depth in 0..max_depth {
+        let mut rec = HitRecord::initialize();
+        if !scene.world.hit(&ray, Interval::new(0.0, INFINITY), &mut rec) {
+            let weight = match scatter_pdf {
+                Some(pdf) if next_event => power_heuristic(pdf, scene.background.pdf(ray.direction())),
+                _ => 1.0,
+            };
+            let background = scene.background.value(ray.direction());
+            if depth == 0 {
+                record_miss(features, background);
+            }
+            let escaped = weight * throughput * background;
+            radiance += escaped;
+            if depth == 1 {
+                direct += escaped;
+            }
+            break;
+        }
+
+        let material = rec.material.clone();
+        if next_event {
+            let color = sample_direct(&ray, &rec, &material, scene, sampler);
+            radiance += throughput * color;
+            if depth == 0 {
+                direct = color;
+            }
+        }
+        if depth == 0 {
+            record_hit(features, &ray, &rec, &material);
+        }
+
+        let mut scattered = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 0.0));
+        let mut attenuation = Vec3::new(0.0, 0.0, 0.0);
+        if !material.scatter(&ray, &mut rec, &mut attenuation, &mut scattered, sampler) {
+            break;
+        }
+        let lobe = material.lobe(&rec, &scattered);
+        lobe_depths[lobe as usize] += 1;
+        if settings.max_lobe_depth(lobe).is_some_and(|max| lobe_depths[lobe as usize] > max) {
+            break;
+        }
+        let pdf = material.pdf(&ray, &rec, unit_vector(scattered.direction()));
+        scatter_pdf = if pdf > 0.0 { Some(pdf) } else { None };
+        throughput = throughput * attenuation;
+        ray = scattered;
+
+        // paths carrying little light are ended at random, and the survivors carry more to make up for
+        // it, so the estimate stays unbiased
+        if depth + 1 >= settings.roulette_depth {
+            let survival = max_component(throughput).min(1.0);
+            if survival <= 0.0 || sampler.get_1d() >= survival {
+                break;
+            }
+            throughput /= survival;
+        }
+    }
+
+    if let Some(max_radiance) = settings.max_radiance {
+        let peak = max_component(radiance);
+        if peak > max_radiance {
+            radiance *= max_radiance / peak;
+            direct *= max_radiance / peak;
+        }
+    }
+    if features.hit {
+        features.direct = direct;
+        features.indirect = radiance - direct;
+    }
+    radiance
+}
@@ -1,9 +1,10 @@
-use crate::rtweekend::INFINITY;
+use crate::rtweekend::{Real, INFINITY};
 
 
+#[derive(Clone, Copy, Debug)]
 pub struct Interval {
-    pub min: f64,
-    pub max: f64,
+    pub min: Real,
+    pub max: Real,
 }
 
 impl Interval {
@@ -14,22 +15,22 @@ impl Interval {
         }
     }
 
-    pub fn new(min: f64, max: f64) -> Self {
+    pub fn new(min: Real, max: Real) -> Self {
         Self{
             min,
             max,
             }
     }
 
-    pub fn contains(&self, x: f64) -> bool {
+    pub fn contains(&self, x: Real) -> bool {
         self.min <= x && x <= self.max
     }
 
-    pub fn surrounds(&self, x: f64) -> bool {
+    pub fn surrounds(&self, x: Real) -> bool {
         self.min < x && x < self.max
     }
 
-    pub fn clamp(&self, x: f64) -> f64 {
+    pub fn clamp(&self, x: Real) -> Real {
         if x < self.min {
             self.min
         } else if x > self.max{
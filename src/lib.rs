@@ -0,0 +1,41 @@
+// Constants are written out at f64 precision and conversions to f32 are no-ops when Real is f32.
+#![cfg_attr(feature = "f32", allow(clippy::excessive_precision, clippy::unnecessary_cast))]
+pub mod vec3;
+pub mod color;
+pub mod ray;
+pub mod hittable;
+pub mod sphere;
+pub mod sphere_group;
+pub mod rtweekend;
+pub mod interval;
+pub mod efloat;
+pub mod camera;
+pub mod material;
+pub mod texture;
+pub mod perlin;
+pub mod image_io;
+pub mod normal_map;
+pub mod sampling;
+pub mod background;
+pub mod environment;
+pub mod onb;
+pub mod light;
+pub mod scene;
+pub mod integrator;
+pub mod bdpt;
+pub mod sky;
+pub mod film;
+pub mod sampler;
+pub mod filter;
+pub mod denoise;
+pub mod aov;
+pub mod tonemap;
+pub mod colorspace;
+pub mod projection;
+pub mod aperture;
+pub mod physical_camera;
+pub mod realistic_lens;
+pub mod animation;
+pub mod stereo;
+pub mod tile;
+pub mod distributed;
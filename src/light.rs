@@ -0,0 +1,216 @@
+use crate::onb::Onb;
+use crate::rtweekend::{Real, degrees_to_radians, PI};
+use crate::sampling::{sample_uniform_cone, sample_uniform_sphere, uniform_cone_pdf};
+use crate::vec3::{Vec3, dot, unit_vector};
+
+pub struct LightSample {
+    // unit vector from the shaded point towards the light
+    pub direction: Vec3,
+    // light arriving at the shaded point, before dividing by pdf
+    pub radiance: Vec3,
+    // distance to the light, infinite for lights at infinity
+    pub distance: Real,
+    pub pdf: Real,
+}
+
+// Light leaving a light, for paths traced out from the lights.
+pub struct Emission {
+    // where it leaves from, None for lights at infinity, whose light arrives along direction everywhere
+    pub origin: Option<Vec3>,
+    // unit direction the light travels in
+    pub direction: Vec3,
+    // intensity for lights at a point, radiance for lights at infinity
+    pub radiance: Vec3,
+    // solid angle density with which direction was picked, 1 when the light only shines one way
+    pub pdf: Real,
+}
+
+// Lights that can't be hit by rays and are only seen through shadow rays.
+pub trait Light {
+    fn sample_li(&self, p: Vec3, u: (Real, Real)) -> Option<LightSample>;
+
+    // Picks a direction for light leaving the light.
+    fn sample_le(&self, u: (Real, Real)) -> Option<Emission>;
+
+    // Solid angle density with which sample_le picks the unit direction it travels in, 0 for lights
+    // that only shine one way.
+    fn pdf_le(&self, direction: Vec3) -> Real;
+}
+
+// Light arriving from a single direction, like the sun. A non-zero angle spreads it over
+// a cone of that apex angle (in degrees) for soft shadows.
+pub struct DirectionalLight {
+    // points from the scene towards the light
+    pub direction: Vec3,
+    pub irradiance: Vec3,
+    pub angle: Real,
+}
+
+impl DirectionalLight {
+    pub fn new(direction: Vec3, irradiance: Vec3, angle: Real) -> Self {
+        DirectionalLight { direction: unit_vector(direction), irradiance, angle }
+    }
+}
+
+impl Light for DirectionalLight {
+    fn sample_li(&self, _p: Vec3, u: (Real, Real)) -> Option<LightSample> {
+        let direction = if self.angle > 0.0 {
+            let cos_theta_max = degrees_to_radians(self.angle / 2.0).cos();
+            Onb::new(self.direction).transform(sample_uniform_cone(u, cos_theta_max))
+        } else {
+            self.direction
+        };
+
+        Some(LightSample {
+            direction,
+            radiance: self.irradiance,
+            distance: Real::INFINITY,
+            pdf: 1.0,
+        })
+    }
+
+    // the irradiance is spread evenly over the cone, as radiance
+    fn sample_le(&self, u: (Real, Real)) -> Option<Emission> {
+        if self.angle <= 0.0 {
+            return Some(Emission { origin: None, direction: -self.direction, radiance: self.irradiance, pdf: 1.0 });
+        }
+        let cos_theta_max = degrees_to_radians(self.angle / 2.0).cos();
+        let direction = Onb::new(self.direction).transform(sample_uniform_cone(u, cos_theta_max));
+        let pdf = uniform_cone_pdf(cos_theta_max);
+        Some(Emission { origin: None, direction: -direction, radiance: pdf * self.irradiance, pdf })
+    }
+
+    fn pdf_le(&self, direction: Vec3) -> Real {
+        let cos_theta_max = degrees_to_radians(self.angle / 2.0).cos();
+        if self.angle <= 0.0 || dot(-direction, self.direction) < cos_theta_max {
+            return 0.0;
+        }
+        uniform_cone_pdf(cos_theta_max)
+    }
+}
+
+// Infinitely small light emitting equally in all directions, with inverse-square falloff.
+pub struct PointLight {
+    pub position: Vec3,
+    // radiant intensity, the irradiance it produces at unit distance
+    pub intensity: Vec3,
+}
+
+impl PointLight {
+    pub fn new(position: Vec3, intensity: Vec3) -> Self {
+        PointLight { position, intensity }
+    }
+}
+
+impl Light for PointLight {
+    fn sample_li(&self, p: Vec3, _u: (Real, Real)) -> Option<LightSample> {
+        let to_light = self.position - p;
+        let distance_squared = to_light.length_squared();
+        if distance_squared == 0.0 {
+            return None;
+        }
+
+        Some(LightSample {
+            direction: to_light / distance_squared.sqrt(),
+            radiance: self.intensity / distance_squared,
+            distance: distance_squared.sqrt(),
+            pdf: 1.0,
+        })
+    }
+
+    fn sample_le(&self, u: (Real, Real)) -> Option<Emission> {
+        Some(Emission {
+            origin: Some(self.position),
+            direction: sample_uniform_sphere(u),
+            radiance: self.intensity,
+            pdf: 1.0 / (4.0 * PI),
+        })
+    }
+
+    fn pdf_le(&self, _direction: Vec3) -> Real {
+        1.0 / (4.0 * PI)
+    }
+}
+
+// Point light restricted to a cone. Full intensity inside falloff_angle, fading out smoothly
+// up to cone_angle. Both are apex angles in degrees.
+pub struct SpotLight {
+    pub position: Vec3,
+    // direction the spot is pointing at
+    pub direction: Vec3,
+    pub intensity: Vec3,
+    pub cone_angle: Real,
+    pub falloff_angle: Real,
+}
+
+impl SpotLight {
+    pub fn new(position: Vec3, look_at: Vec3, intensity: Vec3, cone_angle: Real, falloff_angle: Real) -> Self {
+        SpotLight {
+            position,
+            direction: unit_vector(look_at - position),
+            intensity,
+            cone_angle,
+            falloff_angle: falloff_angle.min(cone_angle),
+        }
+    }
+
+    fn falloff(&self, w: Vec3) -> Real {
+        let cos_theta = dot(w, self.direction);
+        let cos_total = degrees_to_radians(self.cone_angle / 2.0).cos();
+        let cos_start = degrees_to_radians(self.falloff_angle / 2.0).cos();
+        if cos_theta < cos_total {
+            return 0.0;
+        }
+        if cos_theta >= cos_start {
+            return 1.0;
+        }
+        let t = (cos_theta - cos_total) / (cos_start - cos_total);
+        t * t * (3.0 - 2.0 * t)
+    }
+}
+
+impl Light for SpotLight {
+    fn sample_li(&self, p: Vec3, _u: (Real, Real)) -> Option<LightSample> {
+        let to_light = self.position - p;
+        let distance_squared = to_light.length_squared();
+        if distance_squared == 0.0 {
+            return None;
+        }
+        let direction = to_light / distance_squared.sqrt();
+        let falloff = self.falloff(-direction);
+        if falloff == 0.0 {
+            return None;
+        }
+
+        Some(LightSample {
+            direction,
+            radiance: (falloff / distance_squared) * self.intensity,
+            distance: distance_squared.sqrt(),
+            pdf: 1.0,
+        })
+    }
+
+    // directions are picked uniformly over the whole cone, the falloff goes into the intensity
+    fn sample_le(&self, u: (Real, Real)) -> Option<Emission> {
+        let cos_total = degrees_to_radians(self.cone_angle / 2.0).cos();
+        let direction = Onb::new(self.direction).transform(sample_uniform_cone(u, cos_total));
+        let falloff = self.falloff(direction);
+        if falloff == 0.0 {
+            return None;
+        }
+        Some(Emission {
+            origin: Some(self.position),
+            direction,
+            radiance: falloff * self.intensity,
+            pdf: uniform_cone_pdf(cos_total),
+        })
+    }
+
+    fn pdf_le(&self, direction: Vec3) -> Real {
+        let cos_total = degrees_to_radians(self.cone_angle / 2.0).cos();
+        if dot(direction, self.direction) < cos_total {
+            return 0.0;
+        }
+        uniform_cone_pdf(cos_total)
+    }
+}
@@ -1,24 +1,19 @@
 use std::rc::Rc;
-use rand::{random, Rng};
-use camera::Camera;
-use hittable::HittableList;
-use vec3::Vec3;
-use sphere::Sphere;
-use crate::material::{Lambertian, Metal, Dielectric};
-use color::random_color;
-use crate::color::random_color_min_max;
-
-mod vec3;
-mod color;
-mod ray;
-pub mod hittable;
-mod sphere;
-mod rtweekend;
-mod interval;
-mod camera;
-pub mod material;
-
-fn test() {
+use rand::rngs::StdRng;
+use rand::{random, Rng, SeedableRng};
+use raytracing::camera::Camera;
+use raytracing::hittable::HittableList;
+use raytracing::bdpt::Bdpt;
+use raytracing::integrator::PathTracer;
+use raytracing::vec3::Vec3;
+use raytracing::sphere::Sphere;
+use raytracing::sphere_group::SphereGroup;
+use raytracing::material::{Lambertian, Metal, Dielectric};
+use raytracing::color::{random_color, random_color_min_max};
+use raytracing::distributed::{render_distributed, serve, SceneDescription, Workers};
+use raytracing::rtweekend::Real;
+
+fn make_test(_seed: u64) -> (HittableList, Camera) {
     let material_ground = Rc::new(Lambertian::new(Vec3::new(0.8, 0.8, 0.0)));
     let material_center = Rc::new(Lambertian::new(Vec3::new(0.1, 0.2, 0.5)));
     let material_left = Rc::new(Dielectric::new(1.5));
@@ -36,7 +31,7 @@ fn test() {
     cam.aspect_ratio = 16.0 / 9.0;
     cam.image_width = 400;
     cam.sample_per_pixel = 100;
-    cam.max_depth = 50;
+    cam.integrator = Rc::new(PathTracer { max_depth: 50, ..Default::default() });
 
     cam.vfov = 20.0;
     cam.look_from = Vec3::new(-2.0, 2.0, 1.0);
@@ -46,38 +41,42 @@ fn test() {
     cam.defocus_angle = 10.0;
     cam.focus_dist = 3.4;
 
-    cam.render(&world);
+    (world, cam)
 }
 
 
-fn make_cover() {
+fn make_cover(seed: u64) -> (HittableList, Camera) {
+    let mut rng = StdRng::seed_from_u64(seed);
 
     let ground_material = Rc::new(Lambertian::new(Vec3::new(0.5, 0.5, 0.5)));
     let mut world: HittableList = HittableList::new(Box::new(Sphere::new(Vec3::new(0.0, -1000.0, -1.0), 1000.0, ground_material)));
 
+    // the small spheres are intersected together, four at a time with the simd feature
+    let mut small_spheres = SphereGroup::new();
     for a in -11..=11 {
         for b in -11..=11 {
-            let choose_mat = random::<f64>();
-            let center = Vec3::new(a as f64 + 0.9 * random::<f64>(), 0.2, b as f64 + 0.9 * random::<f64>() );
+            let choose_mat = rng.gen::<Real>();
+            let center = Vec3::new(a as Real + 0.9 * rng.gen::<Real>(), 0.2, b as Real + 0.9 * rng.gen::<Real>() );
 
             if (center - Vec3::new(4.0, 0.2, 0.0)).length() > 0.9 {
                 if choose_mat < 0.8 {
-                    let albedo = random_color();
+                    let albedo = random_color(&mut rng);
                     let sphere_material = Rc::new(Lambertian::new(albedo));
-                    world.add(Box::new(Sphere::new(center, 0.2, sphere_material)));
+                    small_spheres.add(Sphere::new(center, 0.2, sphere_material));
                 } else if choose_mat < 0.95 {
-                    let albedo = random_color_min_max(0.5, 1.0);
-                    let fuzz = rand::thread_rng().gen_range(0.0..=0.5);
+                    let albedo = random_color_min_max(&mut rng, 0.5, 1.0);
+                    let fuzz = rng.gen_range(0.0..=0.5);
                     let sphere_material = Rc::new(Metal::new(albedo, fuzz));
-                    world.add(Box::new(Sphere::new(center, 0.2, sphere_material)));
+                    small_spheres.add(Sphere::new(center, 0.2, sphere_material));
                 } else {
                     let sphere_material = Rc::new(Dielectric::new(1.5));
-                    world.add(Box::new(Sphere::new(center, 0.2, sphere_material)));
+                    small_spheres.add(Sphere::new(center, 0.2, sphere_material));
                 }
             }
 
         }
     }
+    world.add(Box::new(small_spheres));
 
     let material1 = Rc::new(Dielectric::new(1.5));
     let material2 = Rc::new(Lambertian::new(Vec3::new(0.4, 0.2, 0.1)));
@@ -93,7 +92,7 @@ fn make_cover() {
     cam.aspect_ratio = 16.0 / 9.0;
     cam.image_width = 1200;
     cam.sample_per_pixel = 500;
-    cam.max_depth = 50;
+    cam.integrator = Rc::new(PathTracer { max_depth: 50, ..Default::default() });
 
     cam.vfov = 20.0;
     cam.look_from = Vec3::new(13.0, 2.0, 3.0);
@@ -103,11 +102,103 @@ fn make_cover() {
     cam.defocus_angle = 0.6;
     cam.focus_dist = 10.0;
 
-    cam.render(&world);
+    (world, cam)
 }
 
+// Every scene that can be rendered by name, which is also how workers rebuild them.
+fn build_scene(name: &str, seed: u64) -> Option<(HittableList, Camera)> {
+    match name {
+        "cover" => Some(make_cover(seed)),
+        "test" => Some(make_test(seed)),
+        _ => None,
+    }
+}
+
+const USAGE: &str = "usage: raytracing [--scene cover|test] [--seed N] [--width N] [--spp N] [--integrator path|bdpt]
+                  [--checkpoint PATH] [--workers N] [--connect ADDRESS]...
+       raytracing worker [--once] [ADDRESS]
+
+ADDRESS is host:port or unix:path. --workers starts that many local worker processes and --connect
+uses workers already listening; with neither the scene is rendered in this process. --integrator
+and --checkpoint only apply to renders in this process, bdpt being the bidirectional path tracer.
+--checkpoint saves progress to PATH while rendering and resumes from it if it is already there.";
+
 fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    if args.first().map(String::as_str) == Some("worker") {
+        let once = args.iter().any(|arg| arg == "--once");
+        let address = args.iter().skip(1).find(|arg| *arg != "--once").map_or("127.0.0.1:7878", String::as_str);
+        if let Err(e) = serve(address, build_scene, once) {
+            eprintln!("Worker stopped... {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
 
-    make_cover();
+    let mut name = String::from("cover");
+    let mut seed = None;
+    let (mut width, mut spp) = (None, None);
+    let mut integrator = None;
+    let mut checkpoint = None;
+    let mut workers = Workers::default();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let value = args.next().unwrap_or_else(|| {
+            eprintln!("{}", USAGE);
+            std::process::exit(2);
+        });
+        let number = || value.parse::<u64>().unwrap_or_else(|_| {
+            eprintln!("{} takes a number\n{}", arg, USAGE);
+            std::process::exit(2);
+        });
+        match arg.as_str() {
+            "--scene" => name = value.clone(),
+            "--seed" => seed = Some(number()),
+            "--width" => width = Some(number() as u32),
+            "--spp" => spp = Some(number() as u32),
+            "--integrator" if value == "path" || value == "bdpt" => integrator = Some(value.clone()),
+            "--checkpoint" => checkpoint = Some(value.clone()),
+            "--workers" => workers.spawn = number() as u32,
+            "--connect" => workers.addresses.push(value.clone()),
+            _ => {
+                eprintln!("{}", USAGE);
+                std::process::exit(2);
+            }
+        }
+    }
 
+    if (integrator.is_some() || checkpoint.is_some()) && (workers.spawn > 0 || !workers.addresses.is_empty()) {
+        eprintln!("{}", USAGE);
+        std::process::exit(2);
+    }
+
+    // a random scene unless asked for a particular one, printed so it can be rendered again
+    let seed = seed.unwrap_or_else(random);
+    println!("Rendering {} with seed {}", name, seed);
+    let Some((world, mut cam)) = build_scene(&name, seed) else {
+        eprintln!("unknown scene {}\n{}", name, USAGE);
+        std::process::exit(2);
+    };
+    let scene = SceneDescription {
+        name,
+        seed,
+        image_width: width.unwrap_or(cam.image_width),
+        sample_per_pixel: spp.unwrap_or(cam.sample_per_pixel),
+    };
+
+    if workers.spawn == 0 && workers.addresses.is_empty() {
+        cam.image_width = scene.image_width;
+        cam.sample_per_pixel = scene.sample_per_pixel;
+        cam.checkpoint = checkpoint;
+        cam.scene_seed = seed;
+        if integrator.as_deref() == Some("bdpt") {
+            // the disk light paths from the sky start on covers the field of small spheres
+            cam.integrator = Rc::new(Bdpt { max_depth: 10, infinite_light_radius: 17.0, ..Default::default() });
+        }
+        cam.render(&world);
+    } else if let Err(e) = render_distributed(&scene, build_scene, &workers) {
+        eprintln!("Distributed render failed... {}", e);
+        std::process::exit(1);
+    }
 }
@@ -1,10 +1,49 @@
-use rand::random;
 use crate::hittable::HitRecord;
 use crate::ray::Ray;
-use crate::vec3::{random_unit_vector, reflect, unit_vector, Vec3, dot, refract};
+use crate::rtweekend::{Real, PI};
+use crate::sampler::Sampler;
+use crate::sampling::sample_uniform_sphere;
+use crate::vec3::{reflect, unit_vector, Vec3, dot, refract};
+
+// Kind of scattering event, for limiting the number of bounces of each kind separately.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Lobe {
+    Diffuse,
+    // reflection off mirrors, metals and glass
+    Specular,
+    // refraction into or out of glass
+    Transmission,
+}
 
 pub trait Material {
-    fn scatter(&self, r_in: &Ray, rec: &mut HitRecord, attenuation: &mut Vec3, scattered: &mut Ray) -> bool;
+    fn scatter(&self, r_in: &Ray, rec: &mut HitRecord, attenuation: &mut Vec3, scattered: &mut Ray, sampler: &mut dyn Sampler) -> bool;
+
+    // BRDF times the cosine term for light arriving from the unit direction wi. Materials
+    // that only scatter into singular directions (mirrors, glass) can't be evaluated and return black.
+    fn eval(&self, _r_in: &Ray, _rec: &HitRecord, _wi: Vec3) -> Vec3 {
+        Vec3::new(0.0, 0.0, 0.0)
+    }
+
+    // Solid angle density with which scatter() picks the unit direction wi, 0 when it can't be evaluated.
+    fn pdf(&self, _r_in: &Ray, _rec: &HitRecord, _wi: Vec3) -> Real {
+        0.0
+    }
+
+    // Overall surface colour, used as a feature for denoising.
+    fn albedo(&self, _rec: &HitRecord) -> Vec3 {
+        Vec3::new(1.0, 1.0, 1.0)
+    }
+
+    // Which kind of event scatter() picked to send out scattered.
+    fn lobe(&self, _rec: &HitRecord, _scattered: &Ray) -> Lobe {
+        Lobe::Diffuse
+    }
+
+    // Whether scatter() picks directions that eval() and pdf() can't describe, so that paths can
+    // only be continued through it by scattering, never joined up with another path.
+    fn is_specular(&self) -> bool {
+        false
+    }
 }
 
 
@@ -17,50 +56,80 @@ impl Lambertian {
 }
 
 impl Material for Lambertian {
-    fn scatter(&self, r_in: &Ray, rec: &mut HitRecord, attenuation: &mut Vec3, scattered: &mut Ray) -> bool {
-        let mut scatter_direction = rec.normal + random_unit_vector();
+    fn scatter(&self, _r_in: &Ray, rec: &mut HitRecord, attenuation: &mut Vec3, scattered: &mut Ray, sampler: &mut dyn Sampler) -> bool {
+        let mut scatter_direction = rec.shading_normal + sample_uniform_sphere(sampler.get_2d());
 
         if scatter_direction.near_zero() {
-            scatter_direction = rec.normal;
+            scatter_direction = rec.shading_normal;
         }
 
-        *scattered = Ray::new(rec.p, scatter_direction);
+        *scattered = rec.spawn_ray(scatter_direction);
         *attenuation = self.albedo;
-        true
+        // a perturbed shading normal can send the ray below the actual surface
+        dot(scatter_direction, rec.normal) > 0.0
+    }
+
+    fn eval(&self, _r_in: &Ray, rec: &HitRecord, wi: Vec3) -> Vec3 {
+        if dot(wi, rec.normal) <= 0.0 {
+            return Vec3::new(0.0, 0.0, 0.0);
+        }
+        let cosine = dot(wi, rec.shading_normal).max(0.0);
+        (cosine / PI) * self.albedo
+    }
+
+    // scatter() picks directions following a cosine distribution around the shading normal
+    fn pdf(&self, _r_in: &Ray, rec: &HitRecord, wi: Vec3) -> Real {
+        dot(wi, rec.shading_normal).max(0.0) / PI
+    }
+
+    fn albedo(&self, _rec: &HitRecord) -> Vec3 {
+        self.albedo
     }
 }
 
 pub struct Metal {
     pub albedo: Vec3,
-    pub fuzz: f64,
+    pub fuzz: Real,
 }
 
 impl Metal {
-    pub fn new(albedo: Vec3, fuzz: f64) -> Self{
+    pub fn new(albedo: Vec3, fuzz: Real) -> Self{
         let mut f = fuzz;
-        if fuzz < 0.0 || fuzz > 1.0 { f = 1.0; }
+        if !(0.0..=1.0).contains(&fuzz) { f = 1.0; }
         Metal{albedo, fuzz: f} }
 }
 impl Material for Metal {
-    fn scatter(&self, r_in: &Ray, rec: &mut HitRecord, attenuation: &mut Vec3, scattered: &mut Ray) -> bool {
-        let reflected: Vec3 = reflect(unit_vector(r_in.direction()), rec.normal);
-        *scattered = Ray::new(rec.p, reflected + self.fuzz * random_unit_vector());
+    fn scatter(&self, r_in: &Ray, rec: &mut HitRecord, attenuation: &mut Vec3, scattered: &mut Ray, sampler: &mut dyn Sampler) -> bool {
+        let reflected: Vec3 = reflect(unit_vector(r_in.direction()), rec.shading_normal);
+        *scattered = rec.spawn_ray(reflected + self.fuzz * sample_uniform_sphere(sampler.get_2d()));
         *attenuation = self.albedo;
         dot(scattered.direction(), rec.normal) > 0.0
     }
+
+    fn albedo(&self, _rec: &HitRecord) -> Vec3 {
+        self.albedo
+    }
+
+    fn lobe(&self, _rec: &HitRecord, _scattered: &Ray) -> Lobe {
+        Lobe::Specular
+    }
+
+    fn is_specular(&self) -> bool {
+        true
+    }
 }
 
 pub struct Dielectric {
-    pub ir: f64,
+    pub ir: Real,
 }
 
 impl Dielectric {
-    pub fn new(ir: f64) -> Self { Dielectric{ir} }
+    pub fn new(ir: Real) -> Self { Dielectric{ir} }
 
 }
 
 impl Material for Dielectric {
-    fn scatter(&self, r_in: &Ray, rec: &mut HitRecord, attenuation: &mut Vec3, scattered: &mut Ray) -> bool {
+    fn scatter(&self, r_in: &Ray, rec: &mut HitRecord, attenuation: &mut Vec3, scattered: &mut Ray, sampler: &mut dyn Sampler) -> bool {
         *attenuation = Vec3::new(1.0, 1.0, 1.0);
 
         let mut refraction_ratio = self.ir;
@@ -71,26 +140,37 @@ impl Material for Dielectric {
 
         let unit_direction = unit_vector(r_in.direction());
 
-        let cos_theta = dot(-unit_direction, rec.normal).min(1.0);
+        let cos_theta = dot(-unit_direction, rec.shading_normal).min(1.0);
         let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
 
         let cannot_refract = refraction_ratio * sin_theta > 1.0;
 
-        let mut direction = Vec3::new(0.0, 0.0, 0.0);
+        let direction = if cannot_refract || reflectance(cos_theta, refraction_ratio) > sampler.get_1d() {
+            reflect(unit_direction, rec.shading_normal)
+        } else {
+            refract(unit_direction, rec.shading_normal, refraction_ratio)
+        };
+
+        *scattered = rec.spawn_ray(direction);
+        true
+
+    }
 
-        if cannot_refract || reflectance(cos_theta, refraction_ratio) > random::<f64>() {
-            direction = reflect(unit_direction, rec.normal);
+    // refracted rays are the ones that cross the surface
+    fn lobe(&self, rec: &HitRecord, scattered: &Ray) -> Lobe {
+        if dot(scattered.direction(), rec.normal) < 0.0 {
+            Lobe::Transmission
         } else {
-            direction = refract(unit_direction, rec.normal, refraction_ratio);
+            Lobe::Specular
         }
+    }
 
-        *scattered = Ray::new(rec.p, direction);
+    fn is_specular(&self) -> bool {
         true
-
     }
 }
 
-pub fn reflectance(cosine: f64, ref_idx: f64) -> f64 {
+pub fn reflectance(cosine: Real, ref_idx: Real) -> Real {
     let mut r0 = (1.0 - ref_idx) / (1.0 + ref_idx);
     r0 = r0 * r0;
     r0 + (1.0 - r0) * (1.0 - cosine).powi(5)
@@ -0,0 +1,90 @@
+use std::rc::Rc;
+use crate::color::luminance;
+use crate::hittable::HitRecord;
+use crate::texture::Texture;
+use crate::vec3::{Vec3, cross, dot, unit_vector};
+use crate::rtweekend::Real;
+
+// Perturbs the shading normal of a hit, leaving the geometric normal untouched.
+pub trait NormalModifier {
+    fn apply(&self, rec: &mut HitRecord);
+}
+
+// outward facing normal of the hit, regardless of which side the ray came from
+fn outward_normal(rec: &HitRecord) -> Vec3 {
+    if rec.front_face { rec.shading_normal } else { -rec.shading_normal }
+}
+
+// Tangent-space normal map, with +Y following the direction of increasing v.
+pub struct NormalMap {
+    pub texture: Rc<dyn Texture>,
+    pub strength: Real,
+}
+
+impl NormalMap {
+    pub fn new(texture: Rc<dyn Texture>, strength: Real) -> Self { NormalMap{texture, strength} }
+}
+
+impl NormalModifier for NormalMap {
+    fn apply(&self, rec: &mut HitRecord) {
+        let n = outward_normal(rec);
+        let tangent = rec.dpdu - dot(n, rec.dpdu) * n;
+        if tangent.near_zero() {
+            return;
+        }
+        let t = unit_vector(tangent);
+        let mut b = cross(n, t);
+        if dot(b, rec.dpdv) < 0.0 {
+            b = -b;
+        }
+
+        let encoded = self.texture.value(rec.u, rec.v, rec.p);
+        let ts = Vec3::new(
+            self.strength * (2.0 * encoded.x() - 1.0),
+            self.strength * (2.0 * encoded.y() - 1.0),
+            2.0 * encoded.z() - 1.0,
+        );
+        let perturbed = ts.x() * t + ts.y() * b + ts.z() * n;
+        if perturbed.near_zero() {
+            return;
+        }
+        rec.set_shading_normal(unit_vector(perturbed));
+    }
+}
+
+// Bump map driven by the luminance of any texture, scaled to a displacement in world units.
+pub struct BumpMap {
+    pub height: Rc<dyn Texture>,
+    pub scale: Real,
+}
+
+impl BumpMap {
+    pub fn new(height: Rc<dyn Texture>, scale: Real) -> Self { BumpMap{height, scale} }
+
+    fn displacement(&self, u: Real, v: Real, p: Vec3) -> Real {
+        self.scale * luminance(self.height.value(u, v, p))
+    }
+}
+
+impl NormalModifier for BumpMap {
+    fn apply(&self, rec: &mut HitRecord) {
+        let du = 0.0005;
+        let dv = 0.0005;
+        let n = outward_normal(rec);
+
+        let displace = self.displacement(rec.u, rec.v, rec.p);
+        let u_displace = self.displacement(rec.u + du, rec.v, rec.p + du * rec.dpdu);
+        let v_displace = self.displacement(rec.u, rec.v + dv, rec.p + dv * rec.dpdv);
+
+        let dpdu = rec.dpdu + ((u_displace - displace) / du) * n;
+        let dpdv = rec.dpdv + ((v_displace - displace) / dv) * n;
+        let mut perturbed = cross(dpdu, dpdv);
+        if perturbed.near_zero() {
+            return;
+        }
+        if dot(perturbed, n) < 0.0 {
+            perturbed = -perturbed;
+        }
+        rec.set_shading_normal(unit_vector(perturbed));
+    }
+}
@@ -0,0 +1,25 @@
+use crate::vec3::{Vec3, cross, unit_vector};
+
+// Orthonormal basis built around a single direction, which becomes the w axis.
+pub struct Onb {
+    axis: [Vec3; 3],
+}
+
+impl Onb {
+    pub fn new(n: Vec3) -> Self {
+        let w = unit_vector(n);
+        let a = if w.x().abs() > 0.9 { Vec3::new(0.0, 1.0, 0.0) } else { Vec3::new(1.0, 0.0, 0.0) };
+        let v = unit_vector(cross(w, a));
+        let u = cross(w, v);
+        Onb { axis: [u, v, w] }
+    }
+
+    pub fn u(&self) -> Vec3 { self.axis[0] }
+    pub fn v(&self) -> Vec3 { self.axis[1] }
+    pub fn w(&self) -> Vec3 { self.axis[2] }
+
+    // Transforms a vector expressed in this basis to world coordinates.
+    pub fn transform(&self, v: Vec3) -> Vec3 {
+        v.x() * self.axis[0] + v.y() * self.axis[1] + v.z() * self.axis[2]
+    }
+}
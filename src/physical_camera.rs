@@ -0,0 +1,55 @@
+use crate::rtweekend::{Real, radians_to_degrees};
+
+// Photographic camera settings, mapped onto the thin lens model. When a camera has these they take the
+// place of vfov and defocus_angle, set the shutter interval and adjust the exposure.
+#[derive(Clone, Copy, Debug)]
+pub struct PhysicalCamera {
+    // in millimetres
+    pub focal_length: Real,
+    // the sensor is cropped to the image aspect ratio, in millimetres
+    pub sensor_width: Real,
+    pub sensor_height: Real,
+    pub f_number: Real,
+    // in seconds
+    pub shutter_speed: Real,
+    pub iso: Real,
+    // size of a world unit in metres, to convert the aperture diameter into scene units
+    pub unit_scale: Real,
+}
+
+impl Default for PhysicalCamera {
+    // a 50mm lens on a full frame sensor at sunny 16 settings
+    fn default() -> Self {
+        PhysicalCamera {
+            focal_length: 50.0,
+            sensor_width: 36.0,
+            sensor_height: 24.0,
+            f_number: 16.0,
+            shutter_speed: 1.0 / 100.0,
+            iso: 100.0,
+            unit_scale: 1.0,
+        }
+    }
+}
+
+impl PhysicalCamera {
+    // Vertical field of view in degrees for an image of the given aspect ratio.
+    pub fn vfov(&self, aspect_ratio: Real) -> Real {
+        let height = self.sensor_height.min(self.sensor_width / aspect_ratio);
+        radians_to_degrees(2.0 * (height / (2.0 * self.focal_length)).atan())
+    }
+
+    // Radius of the entrance pupil in world units.
+    pub fn aperture_radius(&self) -> Real {
+        let diameter_mm = self.focal_length / self.f_number;
+        diameter_mm / 2.0 / 1000.0 / self.unit_scale
+    }
+
+    // Exposure adjustment in stops. A radiance of 1 is taken to be a sunlit scene, so the sunny 16 rule
+    // (f/16 at 1/ISO seconds) leaves the image as bright as the settings-free camera renders it.
+    pub fn exposure(&self) -> Real {
+        let settings = self.shutter_speed * self.iso / (self.f_number * self.f_number);
+        let sunny_16 = 1.0 / (16.0 * 16.0);
+        (settings / sunny_16).log2()
+    }
+}
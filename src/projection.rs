@@ -0,0 +1,64 @@
+use crate::rtweekend::{Real, degrees_to_radians, PI};
+use crate::vec3::Vec3;
+
+// How the camera maps the image onto directions in the scene. All of them look along look_at - look_from
+// with vup pointing up in the image.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Projection {
+    // pinhole or thin lens, with the field of view from vfov
+    Perspective,
+    // parallel rays, for architectural and technical views; height is the view height in world units
+    Orthographic { height: Real },
+    // equidistant fisheye: the angle from the view direction grows linearly with the distance from the
+    // image centre, reaching fov / 2 at the top and bottom edges. Outside that circle is black.
+    Fisheye { fov: Real },
+    // the full sphere, longitude along the width and latitude along the height, for 2:1 images
+    Equirectangular,
+    // six 90 degree faces in a 3:2 grid: right, left, up on the first row, down, front, back on the second.
+    // The side faces have vup at the top, the up and down faces have their front edge along the middle of the image.
+    Cubemap,
+}
+
+impl Projection {
+    // Direction through the point (s, t) of the image, s going right and t down from the top left corner,
+    // in camera space: x right, y up and the camera looking down -z. None where nothing is projected.
+    // Only the panoramic projections are handled here, the planar ones depend on the lens and focus.
+    pub fn direction(&self, s: Real, t: Real, aspect_ratio: Real) -> Option<Vec3> {
+        match *self {
+            Projection::Fisheye { fov } => {
+                let x = (2.0 * s - 1.0) * aspect_ratio;
+                let y = 1.0 - 2.0 * t;
+                let r = (x * x + y * y).sqrt();
+                if r > 1.0 {
+                    return None;
+                }
+                let theta = r * degrees_to_radians(fov.min(360.0)) / 2.0;
+                if r == 0.0 {
+                    return Some(Vec3::new(0.0, 0.0, -1.0));
+                }
+                let sin_theta = theta.sin();
+                Some(Vec3::new(sin_theta * x / r, sin_theta * y / r, -theta.cos()))
+            }
+            Projection::Equirectangular => {
+                let phi = (s - 0.5) * 2.0 * PI;
+                let theta = (0.5 - t) * PI;
+                Some(Vec3::new(theta.cos() * phi.sin(), theta.sin(), -theta.cos() * phi.cos()))
+            }
+            Projection::Cubemap => {
+                let column = ((s * 3.0) as usize).min(2);
+                let row = ((t * 2.0) as usize).min(1);
+                let a = 2.0 * (s * 3.0 - column as Real) - 1.0;
+                let b = 2.0 * (t * 2.0 - row as Real) - 1.0;
+                Some(match row * 3 + column {
+                    0 => Vec3::new(1.0, -b, a),
+                    1 => Vec3::new(-1.0, -b, -a),
+                    2 => Vec3::new(a, 1.0, -b),
+                    3 => Vec3::new(a, -1.0, b),
+                    4 => Vec3::new(a, -b, -1.0),
+                    _ => Vec3::new(-a, -b, 1.0),
+                })
+            }
+            Projection::Perspective | Projection::Orthographic { .. } => None,
+        }
+    }
+}
@@ -1,15 +1,24 @@
-use crate::vec3::Vec3;
+use crate::vec3::{Vec3, dot};
+use crate::rtweekend::Real;
 
+#[derive(Clone, Copy, Debug)]
 pub struct Ray {
     origin: Vec3,
     dir: Vec3,
+    // when the ray was sent during the shutter interval, in seconds
+    tm: Real,
 }
 
 impl Ray {
     pub fn new(origin: Vec3, dir: Vec3) -> Self {
+        Ray::with_time(origin, dir, 0.0)
+    }
+
+    pub fn with_time(origin: Vec3, dir: Vec3, tm: Real) -> Self {
         Ray{
-            origin: origin,
-            dir: dir,
+            origin,
+            dir,
+            tm,
         }
     }
 
@@ -21,7 +30,29 @@ impl Ray {
         self.dir
     }
 
-    pub fn at(&self, t: f64) -> Vec3 {
+    pub fn time(&self) -> Real {
+        self.tm
+    }
+
+    pub fn at(&self, t: Real) -> Vec3 {
         self.origin + t * self.dir
     }
-}
\ No newline at end of file
+}
+// Origin for a ray leaving p, which is only known to within p_error in each coordinate. It is pushed
+// along the geometric normal n just past the box of possible positions, to the side w goes into, and
+// rounded away from the surface, so the ray can't hit the surface it leaves again.
+pub fn offset_ray_origin(p: Vec3, p_error: Vec3, n: Vec3, w: Vec3) -> Vec3 {
+    let distance = dot(n.abs(), p_error);
+    let offset = if dot(w, n) < 0.0 { -distance * n } else { distance * n };
+    let moved = p + offset;
+    let round = |value: Real, offset: Real| {
+        if offset > 0.0 {
+            value.next_up()
+        } else if offset < 0.0 {
+            value.next_down()
+        } else {
+            value
+        }
+    };
+    Vec3::new(round(moved.x(), offset.x()), round(moved.y(), offset.y()), round(moved.z(), offset.z()))
+}
@@ -0,0 +1,314 @@
+use crate::ray::Ray;
+use crate::vec3::{dot, unit_vector, Vec3};
+use crate::rtweekend::Real;
+
+// One spherical interface of a lens prescription, listed from the scene side towards the film.
+// All lengths are in millimetres.
+#[derive(Clone, Copy, Debug)]
+pub struct LensElement {
+    // signed radius of curvature, positive when the centre lies towards the film; 0 marks the aperture stop
+    pub curvature_radius: Real,
+    // axial distance to the next interface towards the film
+    pub thickness: Real,
+    // index of refraction of the medium behind the interface, 0 or 1 for air
+    pub eta: Real,
+    pub aperture_radius: Real,
+}
+
+// Number of radial film segments that get their own exit pupil bounds, and the grid of rear element
+// points traced to find each of them
+const PUPIL_SEGMENTS: usize = 32;
+const PUPIL_GRID: usize = 64;
+
+// Axis aligned rectangle on the plane of the rear element.
+#[derive(Clone, Copy, Debug)]
+struct PupilBounds {
+    min: (Real, Real),
+    max: (Real, Real),
+}
+
+impl PupilBounds {
+    fn area(&self) -> Real {
+        (self.max.0 - self.min.0).max(0.0) * (self.max.1 - self.min.1).max(0.0)
+    }
+}
+
+// A camera lens simulated by tracing rays through every element of a real prescription, after PBRT's
+// RealisticCamera. Vignetting, distortion and field curvature all come from the lens itself.
+// The lens looks down +z in its own space, with the film at z = 0.
+pub struct RealisticLens {
+    elements: Vec<LensElement>,
+    // width of the film in millimetres, the height follows from the image aspect ratio
+    pub sensor_width: Real,
+    // size of a world unit in metres
+    pub unit_scale: Real,
+    sensor_height: Real,
+    exit_pupils: Vec<PupilBounds>,
+}
+
+impl RealisticLens {
+    // The stop is narrowed to aperture_diameter when that is smaller than the prescription allows.
+    pub fn new(elements: Vec<LensElement>, aperture_diameter: Real, sensor_width: Real, unit_scale: Real) -> Self {
+        let mut elements = elements;
+        for element in elements.iter_mut() {
+            if element.curvature_radius == 0.0 {
+                element.aperture_radius = element.aperture_radius.min(aperture_diameter / 2.0);
+            }
+        }
+        RealisticLens {
+            elements,
+            sensor_width,
+            unit_scale,
+            sensor_height: sensor_width,
+            exit_pupils: Vec::new(),
+        }
+    }
+
+    // Parses a prescription in the tabular format PBRT ships its lenses in: one interface per line
+    // with the radius, thickness, index of refraction and aperture diameter; # starts a comment.
+    pub fn parse_prescription(text: &str) -> Vec<LensElement> {
+        text.lines()
+            .map(|line| line.split('#').next().unwrap_or(""))
+            .filter_map(|line| {
+                let values: Vec<Real> = line.split_whitespace().filter_map(|v| v.parse().ok()).collect();
+                if values.len() < 4 {
+                    return None;
+                }
+                Some(LensElement {
+                    curvature_radius: values[0],
+                    thickness: values[1],
+                    eta: values[2],
+                    aperture_radius: values[3] / 2.0,
+                })
+            })
+            .collect()
+    }
+
+    // A 50mm f/2 double Gauss lens (US patent 2,673,491), scaled from the 100mm original.
+    pub fn double_gauss(aperture_diameter: Real, sensor_width: Real, unit_scale: Real) -> Self {
+        let prescription = "
+            29.475   3.76   1.67   25.2
+            84.83    0.12   1      25.2
+            19.275   4.025  1.67   23
+            40.77    3.275  1.699  23
+            12.75    5.705  1      18
+            0        4.5    0      17.1
+            -14.495  1.18   1.603  17
+            40.77    6.065  1.658  20
+            -20.385  0.19   1      20
+            437.065  3.22   1.717  20
+            -39.73   5      1      20";
+        RealisticLens::new(RealisticLens::parse_prescription(prescription), aperture_diameter, sensor_width, unit_scale)
+    }
+
+    fn front_z(&self) -> Real {
+        self.elements.iter().map(|element| element.thickness).sum()
+    }
+
+    fn rear_z(&self) -> Real {
+        self.elements.last().map_or(0.0, |element| element.thickness)
+    }
+
+    fn rear_radius(&self) -> Real {
+        self.elements.last().map_or(0.0, |element| element.aperture_radius)
+    }
+
+    fn medium(&self, index: usize) -> Real {
+        let eta = self.elements[index].eta;
+        if eta == 0.0 { 1.0 } else { eta }
+    }
+
+    // Refracts the ray at one interface whose vertex is at element_z, returning false if it misses
+    // the element's opening or is totally internally reflected. eta is incoming over outgoing index.
+    fn interface(&self, element: &LensElement, element_z: Real, ray: &mut Ray, eta: Real) -> bool {
+        let (t, normal) = if element.curvature_radius == 0.0 {
+            if ray.direction().z() == 0.0 {
+                return false;
+            }
+            ((element_z - ray.origin().z()) / ray.direction().z(), None)
+        } else {
+            let radius = element.curvature_radius;
+            let center = Vec3::new(0.0, 0.0, element_z + radius);
+            let o = ray.origin() - center;
+            let d = ray.direction();
+            let a = dot(d, d);
+            let half_b = dot(o, d);
+            let c = dot(o, o) - radius * radius;
+            let discriminant = half_b * half_b - a * c;
+            if discriminant < 0.0 {
+                return false;
+            }
+            let root = discriminant.sqrt();
+            let (t0, t1) = ((-half_b - root) / a, (-half_b + root) / a);
+            // which of the two crossings is on the lens surface depends on the direction and curvature
+            let t = if (d.z() > 0.0) ^ (radius < 0.0) { t0.min(t1) } else { t0.max(t1) };
+            if t < 0.0 {
+                return false;
+            }
+            let mut n = unit_vector(o + t * d);
+            if dot(n, -d) < 0.0 {
+                n = -n;
+            }
+            (t, Some(n))
+        };
+
+        let p = ray.at(t);
+        if p.x() * p.x() + p.y() * p.y() > element.aperture_radius * element.aperture_radius {
+            return false;
+        }
+        let direction = match normal {
+            None => ray.direction(),
+            Some(n) => {
+                let wi = -unit_vector(ray.direction());
+                let cos_i = dot(n, wi);
+                let sin2_t = eta * eta * (1.0 - cos_i * cos_i).max(0.0);
+                if sin2_t >= 1.0 {
+                    return false;
+                }
+                let cos_t = (1.0 - sin2_t).sqrt();
+                eta * -wi + (eta * cos_i - cos_t) * n
+            }
+        };
+        *ray = Ray::new(p, direction);
+        true
+    }
+
+    // Traces a ray leaving the film towards the scene, in the lens's camera space.
+    pub fn trace_from_film(&self, ray: &Ray) -> Option<Ray> {
+        // the elements are laid out towards -z, so flip into that space and back out at the end
+        let flip = |v: Vec3| Vec3::new(v.x(), v.y(), -v.z());
+        let mut lens_ray = Ray::new(flip(ray.origin()), flip(ray.direction()));
+        let mut element_z = 0.0;
+        for i in (0..self.elements.len()).rev() {
+            let element = self.elements[i];
+            element_z -= element.thickness;
+            let eta_out = if i > 0 { self.medium(i - 1) } else { 1.0 };
+            if !self.interface(&element, element_z, &mut lens_ray, self.medium(i) / eta_out) {
+                return None;
+            }
+        }
+        Some(Ray::new(flip(lens_ray.origin()), flip(lens_ray.direction())))
+    }
+
+    // Traces a ray arriving from the scene towards the film, in the lens's camera space.
+    fn trace_from_scene(&self, ray: &Ray) -> Option<Ray> {
+        let flip = |v: Vec3| Vec3::new(v.x(), v.y(), -v.z());
+        let mut lens_ray = Ray::new(flip(ray.origin()), flip(ray.direction()));
+        let mut element_z = -self.front_z();
+        for i in 0..self.elements.len() {
+            let element = self.elements[i];
+            let eta_in = if i > 0 { self.medium(i - 1) } else { 1.0 };
+            if !self.interface(&element, element_z, &mut lens_ray, eta_in / self.medium(i)) {
+                return None;
+            }
+            element_z += element.thickness;
+        }
+        Some(Ray::new(flip(lens_ray.origin()), flip(lens_ray.direction())))
+    }
+
+    // Principal plane and focal point for a ray that entered parallel to the axis, as z coordinates
+    // in the flipped space the elements are laid out in.
+    fn cardinal_points(ray_in: &Ray, ray_out: &Ray) -> (Real, Real) {
+        let tf = -ray_out.origin().x() / ray_out.direction().x();
+        let tp = (ray_in.origin().x() - ray_out.origin().x()) / ray_out.direction().x();
+        (-ray_out.at(tp).z(), -ray_out.at(tf).z())
+    }
+
+    // Principal planes and focal points of the thick lens approximation, on the scene side first.
+    fn thick_lens(&self) -> Option<([Real; 2], [Real; 2])> {
+        let x = 0.001 * (self.sensor_width * self.sensor_width + self.sensor_height * self.sensor_height).sqrt();
+        let scene_ray = Ray::new(Vec3::new(x, 0.0, self.front_z() + 1.0), Vec3::new(0.0, 0.0, -1.0));
+        let (p0, f0) = RealisticLens::cardinal_points(&scene_ray, &self.trace_from_scene(&scene_ray)?);
+        let film_ray = Ray::new(Vec3::new(x, 0.0, self.rear_z() - 1.0), Vec3::new(0.0, 0.0, 1.0));
+        let (p1, f1) = RealisticLens::cardinal_points(&film_ray, &self.trace_from_film(&film_ray)?);
+        Some(([p0, p1], [f0, f1]))
+    }
+
+    // Effective focal length in millimetres.
+    pub fn focal_length(&self) -> Option<Real> {
+        let (pz, fz) = self.thick_lens()?;
+        Some(fz[0] - pz[0])
+    }
+
+    // Autofocus: moves the film so that a point focus_distance world units in front of it is sharp.
+    pub fn focus(&mut self, focus_distance: Real) {
+        let Some((pz, fz)) = self.thick_lens() else { return };
+        let f = fz[0] - pz[0];
+        let z = -focus_distance * self.unit_scale * 1000.0;
+        let c = (pz[1] - z - pz[0]) * (pz[1] - z - 4.0 * f - pz[0]);
+        if c < 0.0 {
+            return;
+        }
+        let delta = 0.5 * (pz[1] - z + pz[0] - c.sqrt());
+        if let Some(last) = self.elements.last_mut() {
+            last.thickness += delta;
+        }
+    }
+
+    // Fits the film to the image and finds, for rings of film positions, the part of the rear element
+    // rays can leave through. Has to run again whenever the focus changes.
+    pub fn prepare(&mut self, aspect_ratio: Real) {
+        self.sensor_height = self.sensor_width / aspect_ratio;
+        let film_radius = 0.5 * (self.sensor_width * self.sensor_width + self.sensor_height * self.sensor_height).sqrt();
+        self.exit_pupils = (0..PUPIL_SEGMENTS)
+            .map(|i| {
+                let x0 = i as Real / PUPIL_SEGMENTS as Real * film_radius;
+                let x1 = (i + 1) as Real / PUPIL_SEGMENTS as Real * film_radius;
+                self.bound_exit_pupil(x0, x1)
+            })
+            .collect();
+    }
+
+    fn bound_exit_pupil(&self, film_x0: Real, film_x1: Real) -> PupilBounds {
+        let extent = 1.5 * self.rear_radius();
+        let mut bounds = PupilBounds { min: (Real::INFINITY, Real::INFINITY), max: (Real::NEG_INFINITY, Real::NEG_INFINITY) };
+        let mut found = false;
+        let samples = PUPIL_GRID * PUPIL_GRID;
+        for i in 0..samples {
+            let film = Vec3::new(film_x0 + (i as Real + 0.5) / samples as Real * (film_x1 - film_x0), 0.0, 0.0);
+            let rx = -extent + 2.0 * extent * ((i % PUPIL_GRID) as Real + 0.5) / PUPIL_GRID as Real;
+            let ry = -extent + 2.0 * extent * ((i / PUPIL_GRID) as Real + 0.5) / PUPIL_GRID as Real;
+            let rear = Vec3::new(rx, ry, self.rear_z());
+            if self.trace_from_film(&Ray::new(film, rear - film)).is_some() {
+                bounds.min = (bounds.min.0.min(rx), bounds.min.1.min(ry));
+                bounds.max = (bounds.max.0.max(rx), bounds.max.1.max(ry));
+                found = true;
+            }
+        }
+        if !found {
+            return PupilBounds { min: (-extent, -extent), max: (extent, extent) };
+        }
+        // grow by a grid cell so points between the samples aren't cut off
+        let cell = 2.0 * extent / PUPIL_GRID as Real;
+        PupilBounds { min: (bounds.min.0 - cell, bounds.min.1 - cell), max: (bounds.max.0 + cell, bounds.max.1 + cell) }
+    }
+
+    // Ray for the film point (s, t), s going right and t down from the top left corner, in camera
+    // space (x right, y up, looking down +z, millimetres), together with its weight: cos^4 falloff
+    // times the size of the exit pupil relative to the one at the centre. None if the lens blocks it.
+    pub fn generate_ray(&self, s: Real, t: Real, u: (Real, Real)) -> Option<(Ray, Real)> {
+        // the lens forms an inverted image, so the top right of the film sees the bottom left of the scene
+        let film = Vec3::new(-(s - 0.5) * self.sensor_width, -(0.5 - t) * self.sensor_height, 0.0);
+        let r_film = (film.x() * film.x() + film.y() * film.y()).sqrt();
+        let film_radius = 0.5 * (self.sensor_width * self.sensor_width + self.sensor_height * self.sensor_height).sqrt();
+        let index = ((r_film / film_radius * PUPIL_SEGMENTS as Real) as usize).min(PUPIL_SEGMENTS - 1);
+        let bounds = self.exit_pupils.get(index)?;
+
+        // the bounds were found along +x, so rotate them round to the film point
+        let lx = bounds.min.0 + u.0 * (bounds.max.0 - bounds.min.0);
+        let ly = bounds.min.1 + u.1 * (bounds.max.1 - bounds.min.1);
+        let (sin, cos) = if r_film != 0.0 { (film.y() / r_film, film.x() / r_film) } else { (0.0, 1.0) };
+        let rear = Vec3::new(cos * lx - sin * ly, sin * lx + cos * ly, self.rear_z());
+
+        let ray = Ray::new(film, rear - film);
+        let out = self.trace_from_film(&ray)?;
+        let cos_theta = unit_vector(ray.direction()).z();
+        let weight = cos_theta.powi(4) * bounds.area() / self.exit_pupils[0].area();
+        Some((out, weight))
+    }
+
+    // Millimetres in lens space to world units.
+    pub fn world_scale(&self) -> Real {
+        0.001 / self.unit_scale
+    }
+}
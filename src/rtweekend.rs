@@ -1,7 +1,27 @@
+// The floating point type all rendering math is done in: f64, or f32 with the f32 feature for half
+// the memory and bandwidth at the cost of precision.
+#[cfg(not(feature = "f32"))]
+pub type Real = f64;
+#[cfg(feature = "f32")]
+pub type Real = f32;
 
-pub static PI: f64 = 3.1415926535897932385;
-pub static INFINITY: f64 = f64::INFINITY;
+pub static PI: Real = std::f64::consts::PI as Real;
+pub static INFINITY: Real = Real::INFINITY;
 
-pub fn degrees_to_radians(degrees: f64) -> f64 {
+pub fn degrees_to_radians(degrees: Real) -> Real {
     degrees * PI / 180.0
 }
+
+pub fn radians_to_degrees(radians: Real) -> Real {
+    radians * 180.0 / PI
+}
+
+// Bound on the relative rounding error of n floating point operations in a row, as in PBRT: each
+// one is off by at most half an ulp, (1 + eps/2)^n - 1 <= gamma(n).
+pub fn gamma(n: u32) -> Real {
+    let machine_epsilon = 0.5 * Real::EPSILON;
+    n as Real * machine_epsilon / (1.0 - n as Real * machine_epsilon)
+}
+
+// Shadow rays stop this fraction short of their target, so they don't hit the surface of the light.
+pub const SHADOW_EPSILON: Real = 0.0001;
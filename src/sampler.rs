@@ -0,0 +1,324 @@
+// Sample generators handing out the uniform numbers a path consumes, one dimension at a time.
+// Each pixel sample restarts at dimension 0 so the same dimension always drives the same
+// decision along a path (pixel position, lens position, then bounce after bounce).
+
+use crate::rtweekend::Real;
+
+pub trait Sampler {
+    fn start_pixel_sample(&mut self, x: u32, y: u32, sample_index: u32);
+    fn get_1d(&mut self) -> Real;
+    fn get_2d(&mut self) -> (Real, Real);
+    // the seed that, with the pixel and sample index, decides every number handed out
+    fn seed(&self) -> u64;
+}
+
+const ONE_MINUS_EPSILON: Real = 1.0 - Real::EPSILON / 2.0;
+
+fn mix_bits(mut v: u64) -> u64 {
+    v ^= v >> 31;
+    v = v.wrapping_mul(0x7fb5d329728ea185);
+    v ^= v >> 27;
+    v = v.wrapping_mul(0x81dadef4bc2dd44d);
+    v ^= v >> 33;
+    v
+}
+
+fn hash(values: &[u64]) -> u64 {
+    values.iter().fold(0x9e3779b97f4a7c15, |h, &v| mix_bits(h ^ v.wrapping_add(0x9e3779b97f4a7c15)))
+}
+
+fn to_unit(bits: u64) -> Real {
+    ((bits >> 11) as Real * (1.0 / (1u64 << 53) as Real)).min(ONE_MINUS_EPSILON)
+}
+
+// A uniform number in [0,1) picked by hashing values, for choices outside the samplers that still have
+// to come out the same for a seed.
+pub(crate) fn hash_to_unit(values: &[u64]) -> Real {
+    to_unit(hash(values))
+}
+
+// Independent uniform random numbers, derived by hashing so renders are repeatable for a seed.
+pub struct IndependentSampler {
+    pub seed: u64,
+    pixel_key: u64,
+    dimension: u64,
+}
+
+impl IndependentSampler {
+    pub fn new(seed: u64) -> Self {
+        IndependentSampler { seed, pixel_key: 0, dimension: 0 }
+    }
+}
+
+impl Sampler for IndependentSampler {
+    fn start_pixel_sample(&mut self, x: u32, y: u32, sample_index: u32) {
+        self.pixel_key = hash(&[self.seed, x as u64, y as u64, sample_index as u64]);
+        self.dimension = 0;
+    }
+
+    fn get_1d(&mut self) -> Real {
+        self.dimension += 1;
+        to_unit(hash(&[self.pixel_key, self.dimension]))
+    }
+
+    fn get_2d(&mut self) -> (Real, Real) {
+        (self.get_1d(), self.get_1d())
+    }
+
+    fn seed(&self) -> u64 {
+        self.seed
+    }
+}
+
+// Jittered stratification: every dimension of a pixel's samples is split into strata, visited
+// in a different random order for each dimension. Samples past x_samples * y_samples start over
+// with fresh permutations, so progressive renders keep working.
+pub struct StratifiedSampler {
+    pub x_samples: u32,
+    pub y_samples: u32,
+    pub jitter: bool,
+    pub seed: u64,
+    pixel: (u32, u32),
+    sample_index: u32,
+    dimension: u64,
+}
+
+impl StratifiedSampler {
+    pub fn new(x_samples: u32, y_samples: u32, jitter: bool, seed: u64) -> Self {
+        StratifiedSampler {
+            x_samples: x_samples.max(1),
+            y_samples: y_samples.max(1),
+            jitter,
+            seed,
+            pixel: (0, 0),
+            sample_index: 0,
+            dimension: 0,
+        }
+    }
+
+    fn samples_per_pixel(&self) -> u32 {
+        self.x_samples * self.y_samples
+    }
+
+    fn next_key(&mut self) -> u64 {
+        self.dimension += 1;
+        let round = (self.sample_index / self.samples_per_pixel()) as u64;
+        hash(&[self.seed, self.pixel.0 as u64, self.pixel.1 as u64, self.dimension, round])
+    }
+
+    fn jitter_offset(&self, key: u64, salt: u64) -> Real {
+        if self.jitter { to_unit(hash(&[key, self.sample_index as u64, salt])) } else { 0.5 }
+    }
+}
+
+impl Sampler for StratifiedSampler {
+    fn start_pixel_sample(&mut self, x: u32, y: u32, sample_index: u32) {
+        self.pixel = (x, y);
+        self.sample_index = sample_index;
+        self.dimension = 0;
+    }
+
+    fn get_1d(&mut self) -> Real {
+        let key = self.next_key();
+        let count = self.samples_per_pixel();
+        let stratum = permutation_element(self.sample_index % count, count, key);
+        ((stratum as Real + self.jitter_offset(key, 0)) / count as Real).min(ONE_MINUS_EPSILON)
+    }
+
+    fn get_2d(&mut self) -> (Real, Real) {
+        let key = self.next_key();
+        let stratum = permutation_element(self.sample_index % self.samples_per_pixel(), self.samples_per_pixel(), key);
+        let x = stratum % self.x_samples;
+        let y = stratum / self.x_samples;
+        (
+            ((x as Real + self.jitter_offset(key, 0)) / self.x_samples as Real).min(ONE_MINUS_EPSILON),
+            ((y as Real + self.jitter_offset(key, 1)) / self.y_samples as Real).min(ONE_MINUS_EPSILON),
+        )
+    }
+
+    fn seed(&self) -> u64 {
+        self.seed
+    }
+}
+
+// Element i of a random permutation of 0..n picked by seed, without building the permutation
+// (Kensler, "Correlated Multi-Jittered Sampling").
+fn permutation_element(mut i: u32, n: u32, seed: u64) -> u32 {
+    let p = seed as u32;
+    let mut w = n.wrapping_sub(1);
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    loop {
+        i ^= p;
+        i = i.wrapping_mul(0xe170893d);
+        i ^= p >> 16;
+        i ^= (i & w) >> 4;
+        i ^= p >> 8;
+        i = i.wrapping_mul(0x0929eb3f);
+        i ^= p >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | p >> 27);
+        i = i.wrapping_mul(0x6935fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dcb303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e501cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < n {
+            break;
+        }
+    }
+    (i.wrapping_add(p)) % n
+}
+
+const PRIMES: [u64; 32] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53,
+    59, 61, 67, 71, 73, 79, 83, 89, 97, 101, 103, 107, 109, 113, 127, 131,
+];
+
+// Halton sequence, one prime base per dimension, with the digits of each pixel's sequence
+// randomly permuted (Owen scrambling) so neighbouring pixels don't share the same pattern.
+// Dimensions past the prime table wrap around with a different scramble.
+pub struct HaltonSampler {
+    pub seed: u64,
+    pixel_key: u64,
+    sample_index: u64,
+    dimension: u64,
+}
+
+impl HaltonSampler {
+    pub fn new(seed: u64) -> Self {
+        HaltonSampler { seed, pixel_key: 0, sample_index: 0, dimension: 0 }
+    }
+
+    fn owen_scrambled_radical_inverse(base: u64, mut a: u64, seed: u64) -> Real {
+        let inv_base = 1.0 / base as Real;
+        let mut inv_base_m = 1.0;
+        let mut reversed_digits: u64 = 0;
+        let mut digit_index = 0;
+        // stop once further digits no longer change the result in double precision
+        while 1.0 - (base as Real - 1.0) * inv_base_m < 1.0 {
+            let next = a / base;
+            let digit_value = a - next * base;
+            // the permutation of each digit depends on all the digits before it
+            let digit_hash = hash(&[seed, reversed_digits, digit_index]);
+            let digit = permutation_element(digit_value as u32, base as u32, digit_hash) as u64;
+            reversed_digits = reversed_digits.wrapping_mul(base).wrapping_add(digit);
+            inv_base_m *= inv_base;
+            digit_index += 1;
+            a = next;
+        }
+        (inv_base_m * reversed_digits as Real).min(ONE_MINUS_EPSILON)
+    }
+}
+
+impl Sampler for HaltonSampler {
+    fn start_pixel_sample(&mut self, x: u32, y: u32, sample_index: u32) {
+        self.pixel_key = hash(&[self.seed, x as u64, y as u64]);
+        self.sample_index = sample_index as u64;
+        self.dimension = 0;
+    }
+
+    fn get_1d(&mut self) -> Real {
+        let dimension = self.dimension;
+        self.dimension += 1;
+        let base = PRIMES[(dimension % PRIMES.len() as u64) as usize];
+        HaltonSampler::owen_scrambled_radical_inverse(base, self.sample_index, hash(&[self.pixel_key, dimension]))
+    }
+
+    fn get_2d(&mut self) -> (Real, Real) {
+        (self.get_1d(), self.get_1d())
+    }
+
+    fn seed(&self) -> u64 {
+        self.seed
+    }
+}
+
+// Owen-scrambled Sobol points. Pairs of dimensions are padded from the first two Sobol
+// dimensions with an independently shuffled index and scramble for every pair
+// (Burley, "Practical Hash-based Owen Scrambling").
+pub struct SobolSampler {
+    pub seed: u64,
+    pixel_key: u64,
+    sample_index: u32,
+    dimension: u64,
+}
+
+impl SobolSampler {
+    pub fn new(seed: u64) -> Self {
+        SobolSampler { seed, pixel_key: 0, sample_index: 0, dimension: 0 }
+    }
+
+    // the second Sobol dimension, generated by the primitive polynomial x + 1
+    fn sobol_dimension_1(index: u32) -> u32 {
+        let mut result = 0;
+        let mut v: u32 = 1 << 31;
+        let mut i = index;
+        while i != 0 {
+            if i & 1 != 0 {
+                result ^= v;
+            }
+            i >>= 1;
+            v ^= v >> 1;
+        }
+        result
+    }
+
+    fn laine_karras_permutation(mut x: u32, seed: u32) -> u32 {
+        x = x.wrapping_add(seed);
+        x ^= x.wrapping_mul(0x6c50b47c);
+        x ^= x.wrapping_mul(0xb82f1e52);
+        x ^= x.wrapping_mul(0xc7afe638);
+        x ^= x.wrapping_mul(0x8d22f6e6);
+        x
+    }
+
+    fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+        SobolSampler::laine_karras_permutation(x.reverse_bits(), seed).reverse_bits()
+    }
+
+    fn bits_to_unit(bits: u32) -> Real {
+        (bits as Real / 4294967296.0).min(ONE_MINUS_EPSILON)
+    }
+
+    fn pad_seeds(&mut self) -> (u32, u32, u32) {
+        let key = hash(&[self.pixel_key, self.dimension]);
+        self.dimension += 1;
+        (key as u32, (key >> 32) as u32, mix_bits(key) as u32)
+    }
+}
+
+impl Sampler for SobolSampler {
+    fn start_pixel_sample(&mut self, x: u32, y: u32, sample_index: u32) {
+        self.pixel_key = hash(&[self.seed, x as u64, y as u64]);
+        self.sample_index = sample_index;
+        self.dimension = 0;
+    }
+
+    fn get_1d(&mut self) -> Real {
+        let (shuffle, scramble, _) = self.pad_seeds();
+        let index = SobolSampler::nested_uniform_scramble(self.sample_index, shuffle);
+        // the first Sobol dimension is the van der Corput sequence
+        SobolSampler::bits_to_unit(SobolSampler::nested_uniform_scramble(index.reverse_bits(), scramble))
+    }
+
+    fn get_2d(&mut self) -> (Real, Real) {
+        let (shuffle, scramble_x, scramble_y) = self.pad_seeds();
+        let index = SobolSampler::nested_uniform_scramble(self.sample_index, shuffle);
+        (
+            SobolSampler::bits_to_unit(SobolSampler::nested_uniform_scramble(index.reverse_bits(), scramble_x)),
+            SobolSampler::bits_to_unit(SobolSampler::nested_uniform_scramble(SobolSampler::sobol_dimension_1(index), scramble_y)),
+        )
+    }
+
+    fn seed(&self) -> u64 {
+        self.seed
+    }
+}
@@ -0,0 +1,142 @@
+use crate::rtweekend::{Real, PI};
+use crate::vec3::Vec3;
+
+// Piecewise-constant distributions used to importance sample tabulated functions.
+
+pub struct Distribution1D {
+    pub func: Vec<Real>,
+    cdf: Vec<Real>,
+    pub func_int: Real,
+}
+
+impl Distribution1D {
+    pub fn new(func: Vec<Real>) -> Self {
+        let n = func.len();
+        let mut cdf = vec![0.0; n + 1];
+        for i in 1..=n {
+            cdf[i] = cdf[i - 1] + func[i - 1].abs() / n as Real;
+        }
+
+        let func_int = cdf[n];
+        for (i, c) in cdf.iter_mut().enumerate().skip(1) {
+            if func_int == 0.0 {
+                *c = i as Real / n as Real;
+            } else {
+                *c /= func_int;
+            }
+        }
+
+        Distribution1D { func, cdf, func_int }
+    }
+
+    pub fn count(&self) -> usize {
+        self.func.len()
+    }
+
+    // Returns the sampled position in [0,1), its density and the index of the bucket it fell in.
+    pub fn sample_continuous(&self, u: Real) -> (Real, Real, usize) {
+        let offset = self.find_offset(u);
+        let mut du = u - self.cdf[offset];
+        let width = self.cdf[offset + 1] - self.cdf[offset];
+        if width > 0.0 {
+            du /= width;
+        }
+
+        let pdf = if self.func_int > 0.0 { self.func[offset].abs() / self.func_int } else { 0.0 };
+        ((offset as Real + du) / self.count() as Real, pdf, offset)
+    }
+
+    pub fn sample_discrete(&self, u: Real) -> (usize, Real) {
+        let offset = self.find_offset(u);
+        (offset, self.discrete_pdf(offset))
+    }
+
+    pub fn discrete_pdf(&self, index: usize) -> Real {
+        if self.func_int == 0.0 {
+            return 0.0;
+        }
+        self.func[index].abs() / (self.func_int * self.count() as Real)
+    }
+
+    fn find_offset(&self, u: Real) -> usize {
+        let last = self.count().saturating_sub(1);
+        self.cdf.partition_point(|&c| c <= u).saturating_sub(1).min(last)
+    }
+}
+
+pub struct Distribution2D {
+    conditional: Vec<Distribution1D>,
+    marginal: Distribution1D,
+}
+
+impl Distribution2D {
+    // func holds nv rows of nu values each
+    pub fn new(func: &[Real], nu: usize, nv: usize) -> Self {
+        let conditional: Vec<Distribution1D> = (0..nv)
+            .map(|v| Distribution1D::new(func[v * nu..(v + 1) * nu].to_vec()))
+            .collect();
+        let marginal = Distribution1D::new(conditional.iter().map(|c| c.func_int).collect());
+        Distribution2D { conditional, marginal }
+    }
+
+    pub fn sample_continuous(&self, u: (Real, Real)) -> ((Real, Real), Real) {
+        let (d1, pdf1, v) = self.marginal.sample_continuous(u.1);
+        let (d0, pdf0, _) = self.conditional[v].sample_continuous(u.0);
+        ((d0, d1), pdf0 * pdf1)
+    }
+
+    pub fn pdf(&self, p: (Real, Real)) -> Real {
+        let nu = self.conditional[0].count();
+        let nv = self.marginal.count();
+        let iu = ((p.0 * nu as Real) as usize).min(nu - 1);
+        let iv = ((p.1 * nv as Real) as usize).min(nv - 1);
+        if self.marginal.func_int == 0.0 {
+            return 0.0;
+        }
+        self.conditional[iv].func[iu].abs() / self.marginal.func_int
+    }
+}
+
+// Multiple importance sampling weight for one sample from each of two strategies.
+pub fn power_heuristic(f_pdf: Real, g_pdf: Real) -> Real {
+    let f = f_pdf * f_pdf;
+    let g = g_pdf * g_pdf;
+    if f + g == 0.0 {
+        return 0.0;
+    }
+    f / (f + g)
+}
+
+// Uniformly samples a direction inside a cone around +Z whose half angle has cosine cos_theta_max.
+pub fn sample_uniform_cone(u: (Real, Real), cos_theta_max: Real) -> Vec3 {
+    let cos_theta = (1.0 - u.0) + u.0 * cos_theta_max;
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let phi = 2.0 * PI * u.1;
+    Vec3::new(phi.cos() * sin_theta, phi.sin() * sin_theta, cos_theta)
+}
+
+pub fn uniform_cone_pdf(cos_theta_max: Real) -> Real {
+    1.0 / (2.0 * PI * (1.0 - cos_theta_max))
+}
+
+pub fn sample_uniform_sphere(u: (Real, Real)) -> Vec3 {
+    let z = 1.0 - 2.0 * u.0;
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let phi = 2.0 * PI * u.1;
+    Vec3::new(r * phi.cos(), r * phi.sin(), z)
+}
+
+// Shirley-Chiu concentric mapping of the unit square onto the unit disk (z = 0).
+pub fn sample_uniform_disk_concentric(u: (Real, Real)) -> Vec3 {
+    let offset = (2.0 * u.0 - 1.0, 2.0 * u.1 - 1.0);
+    if offset.0 == 0.0 && offset.1 == 0.0 {
+        return Vec3::new(0.0, 0.0, 0.0);
+    }
+
+    let (r, theta) = if offset.0.abs() > offset.1.abs() {
+        (offset.0, (PI / 4.0) * (offset.1 / offset.0))
+    } else {
+        (offset.1, PI / 2.0 - (PI / 4.0) * (offset.0 / offset.1))
+    };
+    Vec3::new(r * theta.cos(), r * theta.sin(), 0.0)
+}
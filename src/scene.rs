@@ -0,0 +1,23 @@
+use std::rc::Rc;
+use crate::background::Background;
+use crate::hittable::{HitRecord, HittableList};
+use crate::interval::Interval;
+use crate::light::Light;
+use crate::vec3::Vec3;
+use crate::rtweekend::{Real, SHADOW_EPSILON};
+
+// Everything a ray can interact with while a frame is rendered.
+pub struct Scene<'a> {
+    pub world: &'a HittableList,
+    pub background: &'a dyn Background,
+    pub lights: &'a [Rc<dyn Light>],
+}
+
+impl Scene<'_> {
+    // Whether anything blocks the path leaving rec along direction for the given distance.
+    pub fn occluded(&self, rec: &HitRecord, direction: Vec3, distance: Real) -> bool {
+        let mut shadow_rec = HitRecord::initialize();
+        let shadow_ray = rec.spawn_ray(direction);
+        self.world.hit(&shadow_ray, Interval::new(0.0, distance * (1.0 - SHADOW_EPSILON)), &mut shadow_rec)
+    }
+}
@@ -0,0 +1,187 @@
+use crate::background::{Background, BackgroundSample};
+use crate::color::xyz_to_rgb;
+use crate::light::DirectionalLight;
+use crate::onb::Onb;
+use crate::rtweekend::{Real, degrees_to_radians, PI};
+use crate::sampling::{sample_uniform_cone, uniform_cone_pdf};
+use crate::vec3::{Vec3, dot, unit_vector};
+
+// angular radius of the sun seen from earth
+const SUN_ANGULAR_RADIUS: Real = 0.00465;
+// luminance of the sun outside the atmosphere, in kcd/m^2 like the sky model
+const SUN_LUMINANCE: Real = 1.6E6;
+
+// Preetham et al. "A Practical Analytic Model for Daylight" clear sky, with a sun disk.
+// +Y is the zenith, -Z points north and +X east.
+pub struct PreethamSky {
+    sun_direction: Vec3,
+    turbidity: Real,
+    // scales the model's kcd/m^2 to scene units
+    pub intensity: Real,
+    // whether the sun is part of the background; turn off when lighting with sun_light() instead
+    pub sun_disk: bool,
+    zenith: [Real; 3],
+    perez: [[Real; 5]; 3],
+    sun_radiance: Vec3,
+}
+
+impl PreethamSky {
+    pub fn new(sun_direction: Vec3, turbidity: Real) -> Self {
+        let sun_direction = unit_vector(sun_direction);
+        let t = turbidity;
+        // the model is only defined with the sun above the horizon
+        let theta_s = sun_direction.y().clamp(0.0, 1.0).acos();
+
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_s);
+        let zenith_y = ((4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192).max(0.0);
+        let (t2, th, th2, th3) = (t * t, theta_s, theta_s * theta_s, theta_s * theta_s * theta_s);
+        let zenith_x = t2 * (0.00166 * th3 - 0.00375 * th2 + 0.00209 * th)
+            + t * (-0.02903 * th3 + 0.06377 * th2 - 0.03202 * th + 0.00394)
+            + (0.11693 * th3 - 0.21196 * th2 + 0.06052 * th + 0.25886);
+        let zenith_yc = t2 * (0.00275 * th3 - 0.00610 * th2 + 0.00317 * th)
+            + t * (-0.04214 * th3 + 0.08970 * th2 - 0.04153 * th + 0.00516)
+            + (0.15346 * th3 - 0.26756 * th2 + 0.06670 * th + 0.26688);
+
+        let perez = [
+            [0.1787 * t - 1.4630, -0.3554 * t + 0.4275, -0.0227 * t + 5.3251, 0.1206 * t - 2.5771, -0.0670 * t + 0.3703],
+            [-0.0193 * t - 0.2592, -0.0665 * t + 0.0008, -0.0004 * t + 0.2125, -0.0641 * t - 0.8989, -0.0033 * t + 0.0452],
+            [-0.0167 * t - 0.2608, -0.0950 * t + 0.0092, -0.0079 * t + 0.2102, -0.0441 * t - 1.6537, -0.0109 * t + 0.0529],
+        ];
+
+        let mut sky = PreethamSky {
+            sun_direction,
+            turbidity,
+            intensity: 0.05,
+            sun_disk: true,
+            zenith: [zenith_y, zenith_x, zenith_yc],
+            perez,
+            sun_radiance: Vec3::new(0.0, 0.0, 0.0),
+        };
+        sky.sun_radiance = sky.compute_sun_radiance();
+        sky
+    }
+
+    // Places the sun from a day of the year (1-365), local solar time in hours and a latitude in degrees.
+    pub fn from_location(day_of_year: u32, hour: Real, latitude: Real, turbidity: Real) -> Self {
+        let declination = degrees_to_radians(23.44) * (2.0 * PI * (284.0 + day_of_year as Real) / 365.0).sin();
+        let hour_angle = degrees_to_radians(15.0 * (hour - 12.0));
+        let lat = degrees_to_radians(latitude);
+
+        let sin_altitude = lat.sin() * declination.sin() + lat.cos() * declination.cos() * hour_angle.cos();
+        let altitude = sin_altitude.clamp(-1.0, 1.0).asin();
+        let cos_azimuth = ((declination.sin() - sin_altitude * lat.sin()) / (altitude.cos() * lat.cos()).max(1E-8))
+            .clamp(-1.0, 1.0);
+        let mut azimuth = cos_azimuth.acos();
+        if hour_angle > 0.0 {
+            azimuth = 2.0 * PI - azimuth;
+        }
+
+        let direction = Vec3::new(
+            altitude.cos() * azimuth.sin(),
+            altitude.sin(),
+            -altitude.cos() * azimuth.cos(),
+        );
+        PreethamSky::new(direction, turbidity)
+    }
+
+    pub fn sun_direction(&self) -> Vec3 {
+        self.sun_direction
+    }
+
+    pub fn turbidity(&self) -> Real {
+        self.turbidity
+    }
+
+    // A directional light matching the sun of this sky, for lighting without the sun disk.
+    pub fn sun_light(&self) -> DirectionalLight {
+        let solid_angle = 2.0 * PI * (1.0 - SUN_ANGULAR_RADIUS.cos());
+        DirectionalLight::new(
+            self.sun_direction,
+            self.intensity * solid_angle * self.sun_radiance,
+            2.0 * SUN_ANGULAR_RADIUS.to_degrees(),
+        )
+    }
+
+    fn perez_function(coefficients: &[Real; 5], cos_theta: Real, gamma: Real) -> Real {
+        let [a, b, c, d, e] = *coefficients;
+        (1.0 + a * (b / cos_theta).exp()) * (1.0 + c * (d * gamma).exp() + e * gamma.cos() * gamma.cos())
+    }
+
+    fn sky_radiance(&self, direction: Vec3) -> Vec3 {
+        // directions below the horizon repeat the horizon
+        let cos_theta = direction.y().max(0.01);
+        let gamma = dot(direction, self.sun_direction).clamp(-1.0, 1.0).acos();
+        let theta_s = self.sun_direction.y().clamp(0.0, 1.0).acos();
+
+        let mut y_xy = [0.0; 3];
+        for (i, value) in y_xy.iter_mut().enumerate() {
+            *value = self.zenith[i] * PreethamSky::perez_function(&self.perez[i], cos_theta, gamma)
+                / PreethamSky::perez_function(&self.perez[i], 1.0, theta_s);
+        }
+
+        let [luminance, x, y] = y_xy;
+        if y <= 0.0 {
+            return Vec3::new(0.0, 0.0, 0.0);
+        }
+        let xyz = Vec3::new(x / y * luminance, luminance, (1.0 - x - y) / y * luminance);
+        let rgb = xyz_to_rgb(xyz);
+        Vec3::new(rgb.x().max(0.0), rgb.y().max(0.0), rgb.z().max(0.0))
+    }
+
+    // Sun colour after extinction through the atmosphere, using Rayleigh and Angstrom aerosol optical depths.
+    fn compute_sun_radiance(&self) -> Vec3 {
+        if self.sun_direction.y() <= 0.0 {
+            return Vec3::new(0.0, 0.0, 0.0);
+        }
+        let elevation = self.sun_direction.y().asin().to_degrees();
+        let air_mass = 1.0 / (self.sun_direction.y() + 0.50572 * (elevation + 6.07995).powf(-1.6364));
+        let beta = 0.04608 * self.turbidity - 0.04586;
+
+        // representative wavelengths for red, green and blue, in micrometres
+        let wavelengths: [Real; 3] = [0.68, 0.55, 0.44];
+        let mut transmittance = [0.0; 3];
+        for (i, lambda) in wavelengths.iter().enumerate() {
+            let rayleigh = 0.008735 * lambda.powf(-4.08);
+            let aerosol = beta * lambda.powf(-1.3);
+            transmittance[i] = (-air_mass * (rayleigh + aerosol)).exp();
+        }
+        SUN_LUMINANCE * Vec3::new(transmittance[0], transmittance[1], transmittance[2])
+    }
+
+    fn in_sun_disk(&self, direction: Vec3) -> bool {
+        self.sun_disk && dot(direction, self.sun_direction) >= SUN_ANGULAR_RADIUS.cos()
+    }
+}
+
+impl Background for PreethamSky {
+    fn value(&self, direction: Vec3) -> Vec3 {
+        let direction = unit_vector(direction);
+        let mut radiance = self.sky_radiance(direction);
+        if self.in_sun_disk(direction) {
+            radiance += self.sun_radiance;
+        }
+        self.intensity * radiance
+    }
+
+    // only the sun is sampled explicitly, the sky is left to scattered rays
+    fn sample(&self, u: (Real, Real)) -> Option<BackgroundSample> {
+        if !self.sun_disk || self.sun_direction.y() <= 0.0 {
+            return None;
+        }
+        let cos_theta_max = SUN_ANGULAR_RADIUS.cos();
+        let direction = Onb::new(self.sun_direction).transform(sample_uniform_cone(u, cos_theta_max));
+        Some(BackgroundSample {
+            direction,
+            radiance: self.value(direction),
+            pdf: uniform_cone_pdf(cos_theta_max),
+        })
+    }
+
+    fn pdf(&self, direction: Vec3) -> Real {
+        if self.sun_direction.y() > 0.0 && self.in_sun_disk(unit_vector(direction)) {
+            uniform_cone_pdf(SUN_ANGULAR_RADIUS.cos())
+        } else {
+            0.0
+        }
+    }
+}
@@ -1,50 +1,138 @@
 use std::rc::Rc;
-use crate::vec3::{Vec3, dot};
+use crate::vec3::Vec3;
 use crate::ray::Ray;
 use crate::hittable::{HitRecord, Hittable};
 use crate::interval::Interval;
 use crate::material::Material;
+use crate::normal_map::NormalModifier;
+use crate::rtweekend::{Real, PI, gamma};
+use crate::efloat::{EFloat, Number};
 
 pub struct Sphere {
     center: Vec3,
-    radius: f64,
+    radius: Real,
     material: Rc<dyn Material>,
+    normal_modifier: Option<Rc<dyn NormalModifier>>,
 }
 
 impl Sphere {
-    pub fn new(center: Vec3, radius: f64, material: Rc<dyn Material>) -> Self {
-        Sphere{center, radius, material}
+    pub fn new(center: Vec3, radius: Real, material: Rc<dyn Material>) -> Self {
+        Sphere{center, radius, material, normal_modifier: None}
+    }
+
+    pub fn with_normal_modifier(mut self, normal_modifier: Rc<dyn NormalModifier>) -> Self {
+        self.normal_modifier = Some(normal_modifier);
+        self
+    }
+
+    pub fn center(&self) -> Vec3 {
+        self.center
+    }
+
+    pub fn radius(&self) -> Real {
+        self.radius
+    }
+
+    // p is a point on the unit sphere centered at the origin.
+    // u: returned value [0,1] of angle around the Y axis from X=-1.
+    // v: returned value [0,1] of angle from Y=-1 to Y=+1.
+    fn get_sphere_uv(p: Vec3) -> (Real, Real) {
+        let theta = (-p.y()).acos();
+        let phi = (-p.z()).atan2(p.x()) + PI;
+        (phi / (2.0 * PI), theta / PI)
+    }
+
+    // partial derivatives of the surface position with respect to u and v
+    fn get_sphere_tangents(&self, local: Vec3) -> (Vec3, Vec3) {
+        let r = self.radius.abs();
+        let sin_theta = (1.0 - (local.y() / r).powi(2)).max(0.0).sqrt().max(1E-6);
+        let dpdu = 2.0 * PI * Vec3::new(local.z(), 0.0, -local.x());
+        let dpdv = PI * Vec3::new(
+            -local.x() * local.y() / (r * sin_theta),
+            r * sin_theta,
+            -local.z() * local.y() / (r * sin_theta),
+        );
+        (dpdu, dpdv)
     }
 }
 
 impl Hittable for Sphere {
     fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
-        let oc: Vec3 = r.origin() - self.center;
-        let a: f64 = r.direction().length_squared();
-        let half_b: f64 = dot(oc, r.direction());
-        let c: f64 = oc.length_squared() - self.radius * self.radius;
-        let discriminant: f64 = half_b * half_b - a * c;
-
-        if discriminant < 0.0 {
+        let Some(root) = sphere_root(r, self.center, self.radius, ray_t) else {
             return false;
-        }
-
-        let sqrtd: f64 = discriminant.sqrt();
-        let mut root: f64 = (-half_b - sqrtd) / a;
-        if !ray_t.surrounds(root) {
-            root = (-half_b + sqrtd) / a;
-            if !ray_t.surrounds(root) {
-                return false;
-            }
-        }
+        };
 
         rec.t = root;
-        rec.p = r.at(rec.t);
-        let outward_normal: Vec3 = (rec.p - self.center) / self.radius;
+        // projecting the hit back onto the sphere makes its error depend only on the rounding of that
+        // projection and of adding the centre back, not on the ray
+        let mut local = r.at(rec.t) - self.center;
+        local *= self.radius.abs() / local.length();
+        rec.p = self.center + local;
+        rec.p_error = gamma(5) * local.abs() + gamma(3) * (local.abs() + self.center.abs());
+        let outward_normal: Vec3 = local / self.radius;
         rec.set_face_normal(r, outward_normal);
+        (rec.u, rec.v) = Sphere::get_sphere_uv(local / self.radius.abs());
+        (rec.dpdu, rec.dpdv) = self.get_sphere_tangents(local);
         rec.material = self.material.clone();
+        if let Some(normal_modifier) = &self.normal_modifier {
+            normal_modifier.apply(rec);
+        }
+
+        true
+
+    }
+}
 
-        return true;
+// The discriminant, half_b and c of the quadratic for where a ray with direction d meets a sphere,
+// with oc the ray's origin relative to the centre and a = |d|^2.
+pub(crate) fn discriminant<T: Number>(oc: [T; 3], d: [T; 3], a: T, radius: T) -> (T, T, T) {
+    let half_b = oc[0] * d[0] + oc[1] * d[1] + oc[2] * d[2];
+    let c = (oc[0] * oc[0] + oc[1] * oc[1] + oc[2] * oc[2]) - radius * radius;
+    // half_b * half_b - a * c cancels catastrophically for rays that start on a large sphere or
+    // graze one, so the discriminant comes from how close the ray passes to the centre instead
+    let scale = half_b / a;
+    let closest = [oc[0] - scale * d[0], oc[1] - scale * d[1], oc[2] - scale * d[2]];
+    let distance = (closest[0] * closest[0] + closest[1] * closest[1] + closest[2] * closest[2]).sqrt();
+    (a * (radius - distance) * (radius + distance), half_b, c)
+}
+
+// Distance along r to the sphere with the given centre and radius: the nearer root inside ray_t, or
+// None. Rays spawned from the sphere start just off it, so the root for the point they leave is about
+// zero and rounding can make it come out positive. Each root carries a bound on its rounding error
+// as in PBRT and is only taken once all of that bound is past ray_t.min.
+pub(crate) fn sphere_root(r: &Ray, center: Vec3, radius: Real, ray_t: Interval) -> Option<Real> {
+    let (origin, direction) = (r.origin(), r.direction());
+    let oc = [origin.x() - center.x(), origin.y() - center.y(), origin.z() - center.z()];
+    let d = [direction.x(), direction.y(), direction.z()];
+    if discriminant(oc, d, direction.length_squared(), radius).0 < 0.0 {
+        return None;
+    }
 
+    bounded_root(origin, direction, center, radius, ray_t)
+}
+
+// The rest of sphere_root, with error bounds, which only the spheres a ray may hit pay for. Kept out
+// of line so the test that rules out the others stays small enough to inline into the loops.
+#[inline(never)]
+fn bounded_root(origin: Vec3, direction: Vec3, center: Vec3, radius: Real, ray_t: Interval) -> Option<Real> {
+    let oc = [
+        EFloat::new(origin.x()) - EFloat::new(center.x()),
+        EFloat::new(origin.y()) - EFloat::new(center.y()),
+        EFloat::new(origin.z()) - EFloat::new(center.z()),
+    ];
+    let d = [direction.x(), direction.y(), direction.z()].map(EFloat::new);
+    let a = d[0] * d[0] + d[1] * d[1] + d[2] * d[2];
+    let (discriminant, half_b, c) = discriminant(oc, d, a, EFloat::new(radius));
+    // the root furthest from zero is accurate and the other follows from their product, c / a
+    let q = if half_b.v < 0.0 { -half_b + discriminant.sqrt() } else { -half_b - discriminant.sqrt() };
+    let (t0, t1) = (q / a, c / q);
+    let (near, far) = if t0.v < t1.v { (t0, t1) } else { (t1, t0) };
+    let inside = |root: EFloat| root.lower_bound() > ray_t.min && root.v < ray_t.max;
+    if inside(near) {
+        Some(near.v)
+    } else if inside(far) {
+        Some(far.v)
+    } else {
+        None
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,229 @@
+#[cfg(feature = "simd")]
+use wide::{CmpGe, CmpGt, CmpLt};
+// four lanes of Real
+#[cfg(all(feature = "simd", not(feature = "f32")))]
+use wide::f64x4 as Lanes;
+#[cfg(all(feature = "simd", feature = "f32"))]
+use wide::f32x4 as Lanes;
+use crate::hittable::{HitRecord, Hittable};
+use crate::interval::Interval;
+use crate::ray::Ray;
+use crate::sphere::Sphere;
+#[cfg(not(feature = "simd"))]
+use crate::sphere::sphere_root;
+use crate::rtweekend::Real;
+#[cfg(feature = "simd")]
+use crate::efloat::{EFloat, Float, Number};
+#[cfg(feature = "simd")]
+use crate::sphere::discriminant;
+
+// Four rays traced together, for callers with coherent rays such as neighbouring camera samples.
+pub struct RayPacket {
+    pub rays: [Ray; 4],
+}
+
+// Many spheres intersected as one object. Their centres and radii are kept in separate arrays, so
+// with the simd feature a ray is tested against four spheres at once, or a packet of four rays against
+// one sphere. Only the closest sphere then fills in the hit record, so the hits and object ids are the
+// same as with the spheres in a HittableList.
+pub struct SphereGroup {
+    spheres: Vec<Sphere>,
+    // padded to a multiple of four with NaN, which never hits
+    center_x: Vec<Real>,
+    center_y: Vec<Real>,
+    center_z: Vec<Real>,
+    radius: Vec<Real>,
+}
+
+impl Default for SphereGroup {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SphereGroup {
+    pub fn new() -> Self {
+        SphereGroup { spheres: Vec::new(), center_x: Vec::new(), center_y: Vec::new(), center_z: Vec::new(), radius: Vec::new() }
+    }
+
+    pub fn add(&mut self, sphere: Sphere) {
+        let count = self.spheres.len();
+        if count.is_multiple_of(4) {
+            for lanes in [&mut self.center_x, &mut self.center_y, &mut self.center_z, &mut self.radius] {
+                lanes.extend([Real::NAN; 4]);
+            }
+        }
+        let (center, radius) = (sphere.center(), sphere.radius());
+        self.center_x[count] = center.x();
+        self.center_y[count] = center.y();
+        self.center_z[count] = center.z();
+        self.radius[count] = radius;
+        self.spheres.push(sphere);
+    }
+
+    pub fn len(&self) -> usize {
+        self.spheres.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.spheres.is_empty()
+    }
+
+    // Index of the closest sphere r hits within ray_t, and the distance to it. With the simd feature
+    // the lanes go through the same arithmetic as sphere_root, so both agree with Sphere::hit.
+    #[cfg(not(feature = "simd"))]
+    fn closest(&self, r: &Ray, ray_t: Interval) -> Option<(usize, Real)> {
+        let mut closest = None;
+        let mut closest_t = ray_t.max;
+        for (index, sphere) in self.spheres.iter().enumerate() {
+            if let Some(root) = sphere_root(r, sphere.center(), sphere.radius(), Interval::new(ray_t.min, closest_t)) {
+                closest = Some((index, root));
+                closest_t = root;
+            }
+        }
+        closest
+    }
+
+    #[cfg(feature = "simd")]
+    fn closest(&self, r: &Ray, ray_t: Interval) -> Option<(usize, Real)> {
+        let (origin, direction) = (r.origin(), r.direction());
+        let o = [Lanes::splat(origin.x()), Lanes::splat(origin.y()), Lanes::splat(origin.z())];
+        let d = [Lanes::splat(direction.x()), Lanes::splat(direction.y()), Lanes::splat(direction.z())];
+        let a = Lanes::splat(direction.length_squared());
+
+        let mut closest = None;
+        let mut closest_t = ray_t.max;
+        for chunk in 0..self.center_x.len() / 4 {
+            let lanes = chunk * 4..chunk * 4 + 4;
+            let center = [
+                Lanes::from(&self.center_x[lanes.clone()]),
+                Lanes::from(&self.center_y[lanes.clone()]),
+                Lanes::from(&self.center_z[lanes.clone()]),
+            ];
+            let radius = Lanes::from(&self.radius[lanes]);
+            let oc = [o[0] - center[0], o[1] - center[1], o[2] - center[2]];
+            let hits = discriminant(oc, d, a, radius).0.cmp_ge(Lanes::splat(0.0));
+            if hits.none() {
+                continue;
+            }
+            let roots = lane_roots(o, center, d, radius, hits, ray_t);
+            for (lane, root) in roots.to_array().into_iter().enumerate() {
+                if root < closest_t {
+                    closest = Some((chunk * 4 + lane, root));
+                    closest_t = root;
+                }
+            }
+        }
+        closest
+    }
+
+    // Traces four rays at once, filling in the records of those that hit something.
+    pub fn hit_packet(&self, packet: &RayPacket, ray_t: Interval, recs: &mut [HitRecord; 4]) -> [bool; 4] {
+        let closest = self.closest_packet(packet, ray_t);
+        let mut hits = [false; 4];
+        for lane in 0..4 {
+            if let Some(index) = closest[lane] {
+                if self.spheres[index].hit(&packet.rays[lane], ray_t, &mut recs[lane]) {
+                    recs[lane].object_id = index as u32;
+                    hits[lane] = true;
+                }
+            }
+        }
+        hits
+    }
+
+    #[cfg(not(feature = "simd"))]
+    fn closest_packet(&self, packet: &RayPacket, ray_t: Interval) -> [Option<usize>; 4] {
+        packet.rays.each_ref().map(|r| self.closest(r, ray_t).map(|(index, _)| index))
+    }
+
+    // Every sphere is tested against all four rays, keeping the closest per ray.
+    #[cfg(feature = "simd")]
+    fn closest_packet(&self, packet: &RayPacket, ray_t: Interval) -> [Option<usize>; 4] {
+        let lanes = |f: fn(&Ray) -> Real| Lanes::from(packet.rays.each_ref().map(f));
+        let o = [lanes(|r| r.origin().x()), lanes(|r| r.origin().y()), lanes(|r| r.origin().z())];
+        let d = [lanes(|r| r.direction().x()), lanes(|r| r.direction().y()), lanes(|r| r.direction().z())];
+        let a = lanes(|r| r.direction().length_squared());
+
+        let mut closest_t = Lanes::splat(ray_t.max);
+        let mut closest_index = Lanes::splat(-1.0);
+        for index in 0..self.spheres.len() {
+            let center = [Lanes::splat(self.center_x[index]), Lanes::splat(self.center_y[index]), Lanes::splat(self.center_z[index])];
+            let radius = Lanes::splat(self.radius[index]);
+            let oc = [o[0] - center[0], o[1] - center[1], o[2] - center[2]];
+            let hits = discriminant(oc, d, a, radius).0.cmp_ge(Lanes::splat(0.0));
+            if hits.none() {
+                continue;
+            }
+            let roots = lane_roots(o, center, d, radius, hits, ray_t);
+            let closer = roots.cmp_lt(closest_t);
+            closest_t = closer.blend(roots, closest_t);
+            closest_index = closer.blend(Lanes::splat(index as Real), closest_index);
+        }
+        closest_index.to_array().map(|index| if index < 0.0 { None } else { Some(index as usize) })
+    }
+}
+
+#[cfg(feature = "simd")]
+impl Number for Lanes {
+    fn sqrt(self) -> Self {
+        Lanes::sqrt(self)
+    }
+}
+
+#[cfg(feature = "simd")]
+impl Float for Lanes {
+    fn splat(value: Real) -> Self {
+        Lanes::splat(value)
+    }
+
+    fn abs(self) -> Self {
+        Lanes::abs(self)
+    }
+
+    fn max(self, other: Self) -> Self {
+        Lanes::max(self, other)
+    }
+}
+
+// The nearer root inside ray_t in each lane that hits, or infinity where there is none. The lanes go
+// through the same arithmetic as sphere_root, error bounds included, so they agree on every root.
+#[cfg(feature = "simd")]
+fn lane_roots(o: [Lanes; 3], center: [Lanes; 3], d: [Lanes; 3], radius: Lanes, hits: Lanes, ray_t: Interval) -> Lanes {
+    let blend = |mask: Lanes, t: EFloat<Lanes>, f: EFloat<Lanes>| EFloat { v: mask.blend(t.v, f.v), err: mask.blend(t.err, f.err) };
+    let oc = [
+        EFloat::new(o[0]) - EFloat::new(center[0]),
+        EFloat::new(o[1]) - EFloat::new(center[1]),
+        EFloat::new(o[2]) - EFloat::new(center[2]),
+    ];
+    let d = d.map(EFloat::new);
+    let a = d[0] * d[0] + d[1] * d[1] + d[2] * d[2];
+    let (discriminant, half_b, c) = discriminant(oc, d, a, EFloat::new(radius));
+    let sqrtd = discriminant.sqrt();
+    let q = blend(half_b.v.cmp_lt(Lanes::splat(0.0)), -half_b + sqrtd, -half_b - sqrtd);
+    let (t0, t1) = (q / a, c / q);
+    let ordered = t0.v.cmp_lt(t1.v);
+    let near = blend(ordered, t0, t1);
+    let far = blend(ordered, t1, t0);
+    let inside = |root: EFloat<Lanes>| root.lower_bound().cmp_gt(Lanes::splat(ray_t.min)) & root.v.cmp_lt(Lanes::splat(ray_t.max));
+    let near_inside = hits & inside(near);
+    let far_inside = hits & inside(far);
+    let root = near_inside.blend(near.v, far.v);
+    (near_inside | far_inside).blend(root, Lanes::splat(Real::INFINITY))
+}
+
+impl Hittable for SphereGroup {
+    fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
+        match self.closest(r, ray_t) {
+            Some((index, _)) if self.spheres[index].hit(r, ray_t, rec) => {
+                rec.object_id = index as u32;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn object_count(&self) -> u32 {
+        self.spheres.len() as u32
+    }
+}
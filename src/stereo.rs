@@ -0,0 +1,73 @@
+use crate::image_io::Image;
+use crate::vec3::Vec3;
+use crate::rtweekend::Real;
+
+// How the two eyes are put together into one image.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum StereoLayout {
+    // left eye on the left, the image twice as wide
+    SideBySide,
+    // left eye on top, the image twice as tall
+    OverUnder,
+    // red from the left eye, green and blue from the right, for red/cyan glasses
+    Anaglyph,
+}
+
+// Stereo pair settings. The eyes sit either side of look_from along the camera's horizontal axis and
+// look in parallel, with their images shifted so that things at the convergence distance end up in
+// the same place in both. Panoramic projections render omni-directional stereo instead, where every
+// direction gets its own pair of eyes on a circle around look_from.
+#[derive(Clone, Copy, Debug)]
+pub struct Stereo {
+    // distance between the eyes in world units
+    pub interocular: Real,
+    // distance at which objects have no parallax and appear at the depth of the screen
+    pub convergence: Real,
+    pub layout: StereoLayout,
+}
+
+impl Default for Stereo {
+    // eyes 6.4cm apart with a scale of one unit per metre, converging 10 units away
+    fn default() -> Self {
+        Stereo { interocular: 0.064, convergence: 10.0, layout: StereoLayout::SideBySide }
+    }
+}
+
+impl Stereo {
+    pub fn compose(&self, left: &Image, right: &Image) -> Image {
+        let (width, height) = (left.width, left.height);
+        match self.layout {
+            StereoLayout::SideBySide => {
+                let mut image = Image::new(2 * width, height);
+                for y in 0..height {
+                    for x in 0..width {
+                        image.set_pixel(x, y, left.pixel(x, y));
+                        image.set_pixel(width + x, y, right.pixel(x, y));
+                    }
+                }
+                image
+            }
+            StereoLayout::OverUnder => {
+                let mut pixels = left.pixels.clone();
+                pixels.extend_from_slice(&right.pixels);
+                Image { width, height: 2 * height, pixels }
+            }
+            StereoLayout::Anaglyph => {
+                let pixels = left.pixels.iter()
+                    .zip(right.pixels.iter())
+                    .map(|(l, r)| Vec3::new(l.x(), r.y(), r.z()))
+                    .collect();
+                Image { width, height, pixels }
+            }
+        }
+    }
+}
+
+// Path for one eye's copy of an output, with the eye's name added before the extension. Animation
+// numbers each frame's checkpoint the same way.
+pub fn eye_path(path: &str, eye: &str) -> String {
+    match path.rsplit_once('.') {
+        Some((stem, extension)) if !extension.contains('/') => format!("{}_{}.{}", stem, eye, extension),
+        _ => format!("{}_{}", path, eye),
+    }
+}
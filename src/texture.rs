@@ -0,0 +1,86 @@
+use std::io;
+use std::path::Path;
+use rand::Rng;
+use crate::colorspace::ColorSpace;
+use crate::image_io::{load_color_image, load_image, Image};
+use crate::interval::Interval;
+use crate::perlin::Perlin;
+use crate::vec3::Vec3;
+use crate::rtweekend::Real;
+
+pub trait Texture {
+    fn value(&self, u: Real, v: Real, p: Vec3) -> Vec3;
+}
+
+pub struct SolidColor {
+    pub albedo: Vec3,
+}
+
+impl SolidColor {
+    pub fn new(albedo: Vec3) -> Self { SolidColor{albedo} }
+}
+
+impl Texture for SolidColor {
+    fn value(&self, _u: Real, _v: Real, _p: Vec3) -> Vec3 {
+        self.albedo
+    }
+}
+
+pub struct ImageTexture {
+    image: Image,
+}
+
+impl ImageTexture {
+    pub fn new(image: Image) -> Self { ImageTexture{image} }
+
+    // Loads the stored values as they are, for data like normal maps and heights.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(ImageTexture::new(load_image(path)?))
+    }
+
+    // Loads a colour texture, linearised and converted into the working space.
+    pub fn load_color<P: AsRef<Path>>(path: P, working_space: ColorSpace) -> io::Result<Self> {
+        Ok(ImageTexture::new(load_color_image(path, working_space)?))
+    }
+}
+
+impl Texture for ImageTexture {
+    // bilinear lookup, v = 0 is the bottom row of the image
+    fn value(&self, u: Real, v: Real, _p: Vec3) -> Vec3 {
+        if self.image.width == 0 || self.image.height == 0 {
+            return Vec3::new(0.0, 1.0, 1.0);
+        }
+
+        let unit = Interval::new(0.0, 1.0);
+        let x = unit.clamp(u) * (self.image.width - 1) as Real;
+        let y = (1.0 - unit.clamp(v)) * (self.image.height - 1) as Real;
+
+        let x0 = x.floor() as usize;
+        let y0 = y.floor() as usize;
+        let x1 = (x0 + 1).min(self.image.width - 1);
+        let y1 = (y0 + 1).min(self.image.height - 1);
+        let tx = x - x0 as Real;
+        let ty = y - y0 as Real;
+
+        let top = (1.0 - tx) * self.image.pixel(x0, y0) + tx * self.image.pixel(x1, y0);
+        let bottom = (1.0 - tx) * self.image.pixel(x0, y1) + tx * self.image.pixel(x1, y1);
+        (1.0 - ty) * top + ty * bottom
+    }
+}
+
+// Marbled Perlin turbulence, mostly there as a procedural height for BumpMap.
+pub struct NoiseTexture {
+    noise: Perlin,
+    scale: Real,
+}
+
+impl NoiseTexture {
+    pub fn new<R: Rng>(rng: &mut R, scale: Real) -> Self { NoiseTexture{noise: Perlin::new(rng), scale} }
+}
+
+impl Texture for NoiseTexture {
+    fn value(&self, _u: Real, _v: Real, p: Vec3) -> Vec3 {
+        let s = 0.5 * (1.0 + (self.scale * p.z() + 10.0 * self.noise.turb(p, 7)).sin());
+        Vec3::new(s, s, s)
+    }
+}
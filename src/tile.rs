@@ -0,0 +1,190 @@
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use crate::film::Film;
+use crate::rtweekend::Real;
+
+// Rectangle of pixels, from x0, y0 up to but not including x1, y1.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Bounds {
+    pub x0: u32,
+    pub y0: u32,
+    pub x1: u32,
+    pub y1: u32,
+}
+
+impl Bounds {
+    pub fn width(&self) -> u32 {
+        self.x1 - self.x0
+    }
+
+    pub fn height(&self) -> u32 {
+        self.y1 - self.y0
+    }
+
+    pub fn contains(&self, x: u32, y: u32) -> bool {
+        x >= self.x0 && x < self.x1 && y >= self.y0 && y < self.y1
+    }
+
+    // Whether other is a rectangle of at least one pixel lying entirely inside this one.
+    pub fn encloses(&self, other: &Bounds) -> bool {
+        other.x0 < other.x1 && other.y0 < other.y1
+            && other.x0 >= self.x0 && other.x1 <= self.x1
+            && other.y0 >= self.y0 && other.y1 <= self.y1
+    }
+
+    // Splits the rectangle into square tiles in scanline order, smaller along the right and bottom edges.
+    pub fn tiles(&self, size: u32) -> Vec<Bounds> {
+        let size = size.max(1);
+        let mut tiles = Vec::new();
+        for y0 in (self.y0..self.y1).step_by(size as usize) {
+            for x0 in (self.x0..self.x1).step_by(size as usize) {
+                tiles.push(Bounds { x0, y0, x1: (x0 + size).min(self.x1), y1: (y0 + size).min(self.y1) });
+            }
+        }
+        tiles
+    }
+}
+
+// Part of the image to render, given as fractions of its width and height from the top left corner.
+#[derive(Clone, Copy, Debug)]
+pub struct CropWindow {
+    pub x0: Real,
+    pub y0: Real,
+    pub x1: Real,
+    pub y1: Real,
+    // write the full size image with black outside the window, instead of only the window
+    pub keep_canvas: bool,
+}
+
+impl CropWindow {
+    // Pixels covered by the window, always at least one.
+    pub fn bounds(&self, width: u32, height: u32) -> Bounds {
+        let to_pixel = |f: Real, size: u32| ((f.clamp(0.0, 1.0) * size as Real).round() as u32).min(size - 1);
+        let (x0, y0) = (to_pixel(self.x0, width), to_pixel(self.y0, height));
+        let x1 = ((self.x1.clamp(0.0, 1.0) * width as Real).round() as u32).clamp(x0 + 1, width);
+        let y1 = ((self.y1.clamp(0.0, 1.0) * height as Real).round() as u32).clamp(y0 + 1, height);
+        Bounds { x0, y0, x1, y1 }
+    }
+}
+
+const MAGIC: &[u8] = b"RTCHECKPOINT 4\n";
+
+// What a checkpoint has to match to be resumed: the image and how it is tiled, the samples per
+// pixel, the seeds of the sampler and of the scene, and the shutter interval, which tells the
+// frames of an animation apart.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct CheckpointHeader {
+    pub width: u32,
+    pub height: u32,
+    pub region: Bounds,
+    pub tile_size: u32,
+    pub sample_per_pixel: u32,
+    pub sampler_seed: u64,
+    pub scene_seed: u64,
+    pub shutter: (Real, Real),
+}
+
+impl CheckpointHeader {
+    fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let region = self.region;
+        for value in [self.width, self.height, region.x0, region.y0, region.x1, region.y1, self.tile_size, self.sample_per_pixel] {
+            writer.write_all(&value.to_le_bytes())?;
+        }
+        writer.write_all(&self.sampler_seed.to_le_bytes())?;
+        writer.write_all(&self.scene_seed.to_le_bytes())?;
+        // stored as f64 like the film, a no-op in f64 builds
+        for time in [self.shutter.0, self.shutter.1] {
+            #[allow(clippy::unnecessary_cast)]
+            writer.write_all(&(time as f64).to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    fn read<R: Read>(reader: &mut R) -> io::Result<CheckpointHeader> {
+        let mut read_u32 = || -> io::Result<u32> {
+            let mut bytes = [0; 4];
+            reader.read_exact(&mut bytes)?;
+            Ok(u32::from_le_bytes(bytes))
+        };
+        let (width, height) = (read_u32()?, read_u32()?);
+        let region = Bounds { x0: read_u32()?, y0: read_u32()?, x1: read_u32()?, y1: read_u32()? };
+        let (tile_size, sample_per_pixel) = (read_u32()?, read_u32()?);
+        let mut seeds = [0; 16];
+        reader.read_exact(&mut seeds)?;
+        let mut shutter = [0; 16];
+        reader.read_exact(&mut shutter)?;
+        Ok(CheckpointHeader {
+            width,
+            height,
+            region,
+            tile_size,
+            sample_per_pixel,
+            sampler_seed: u64::from_le_bytes(seeds[..8].try_into().unwrap()),
+            scene_seed: u64::from_le_bytes(seeds[8..].try_into().unwrap()),
+            shutter: (
+                f64::from_le_bytes(shutter[..8].try_into().unwrap()) as Real,
+                f64::from_le_bytes(shutter[8..].try_into().unwrap()) as Real,
+            ),
+        })
+    }
+}
+
+// Render state saved between tiles: the pass in progress, the samples it gives each pixel, which of
+// its tiles are done and the film so far.
+pub struct Checkpoint {
+    pub pass: u32,
+    pub plan: Vec<u32>,
+    pub finished: Vec<bool>,
+    pub film: Film,
+}
+
+impl Checkpoint {
+    // Loads a checkpoint, failing if it was written with a different header.
+    pub fn read<P: AsRef<Path>>(path: P, header: &CheckpointHeader) -> io::Result<Checkpoint> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut magic = vec![0; MAGIC.len()];
+        reader.read_exact(&mut magic)?;
+        if magic != MAGIC {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "not a render checkpoint"));
+        }
+        if CheckpointHeader::read(&mut reader)? != *header {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "checkpoint is for a different image size, region, tile size, sample count, seed or shutter",
+            ));
+        }
+        let mut read_u32 = || -> io::Result<u32> {
+            let mut bytes = [0; 4];
+            reader.read_exact(&mut bytes)?;
+            Ok(u32::from_le_bytes(bytes))
+        };
+        let pass = read_u32()?;
+        let plan = (0..header.width * header.height).map(|_| read_u32()).collect::<io::Result<Vec<u32>>>()?;
+        let tile_count = header.region.tiles(header.tile_size).len();
+        let mut finished = vec![0; tile_count];
+        reader.read_exact(&mut finished)?;
+        let film = Film::read_pixels(header.width, header.height, &mut reader)?;
+        Ok(Checkpoint { pass, plan, finished: finished.iter().map(|&done| done != 0).collect(), film })
+    }
+}
+
+// Saves the render state. It is written next to the path first and then moved over it, so an
+// interruption while writing leaves the previous checkpoint intact.
+pub fn write_checkpoint<P: AsRef<Path>>(path: P, header: &CheckpointHeader, pass: u32, plan: &[u32], finished: &[bool], film: &Film) -> io::Result<()> {
+    let path = path.as_ref();
+    let temporary = path.with_extension("partial");
+    let mut buffer = BufWriter::new(File::create(&temporary)?);
+    buffer.write_all(MAGIC)?;
+    header.write(&mut buffer)?;
+    buffer.write_all(&pass.to_le_bytes())?;
+    for samples in plan {
+        buffer.write_all(&samples.to_le_bytes())?;
+    }
+    let finished: Vec<u8> = finished.iter().map(|&done| done as u8).collect();
+    buffer.write_all(&finished)?;
+    film.write_pixels(&mut buffer)?;
+    buffer.flush()?;
+    drop(buffer);
+    fs::rename(temporary, path)
+}
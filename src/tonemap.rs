@@ -0,0 +1,172 @@
+use crate::color::{luminance, rgb_to_xyz, xyz_to_rgb};
+use crate::colorspace::{transform, ColorSpace, Matrix3};
+use crate::image_io::Image;
+use crate::vec3::Vec3;
+use crate::rtweekend::Real;
+
+// Operators that compress scene radiance into the displayable [0,1] range.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ToneMapper {
+    // cut every channel off at 1
+    Clamp,
+    // L / (1 + L) on the luminance, which never quite reaches white
+    Reinhard,
+    // Reinhard with a luminance that maps exactly to white
+    ExtendedReinhard { white: Real },
+    // Stephen Hill's fit of the ACES reference rendering and sRGB output transforms
+    AcesFilmic,
+    // Troy Sobotka's AgX, with the polynomial approximation of its base contrast curve
+    AgX,
+}
+
+// Turns the linear film into display values: exposure, white balance, then the tone mapper, all in
+// linear sRGB. The result still has to go through the sRGB transfer curve, which write_color does.
+#[derive(Clone, Copy, Debug)]
+pub struct ToneMapping {
+    // exposure compensation in stops, +1 doubles the brightness
+    pub exposure: Real,
+    // colour temperature in kelvin of the light that should come out neutral, e.g. 3200 for tungsten
+    pub white_balance: Option<Real>,
+    pub tone_mapper: ToneMapper,
+}
+
+impl Default for ToneMapping {
+    fn default() -> Self {
+        ToneMapping { exposure: 0.0, white_balance: None, tone_mapper: ToneMapper::Clamp }
+    }
+}
+
+impl ToneMapping {
+    // Tone maps a linear sRGB colour. The result isn't clamped yet: colours outside the sRGB gamut
+    // come out with negative channels, which a wider output space can still show.
+    pub fn apply(&self, color: Vec3) -> Vec3 {
+        let mut color = Real::powf(2.0, self.exposure) * color;
+        if let Some(temperature) = self.white_balance {
+            color = white_balance(color, temperature);
+        }
+        match self.tone_mapper {
+            ToneMapper::Clamp => color,
+            ToneMapper::Reinhard => scale_luminance(color, |l| l / (1.0 + l)),
+            ToneMapper::ExtendedReinhard { white } => {
+                scale_luminance(color, |l| l * (1.0 + l / (white * white)) / (1.0 + l))
+            }
+            ToneMapper::AcesFilmic => aces_filmic(color),
+            ToneMapper::AgX => agx(color),
+        }
+    }
+
+    // Tone maps an image in the working space for display in the output space. Values are only clamped
+    // to [0,1] once they are in the output space, so a wide gamut output keeps saturated colours.
+    pub fn apply_image(&self, image: &Image, working_space: ColorSpace, output_space: ColorSpace) -> Image {
+        let display = |pixel: Vec3| {
+            let color = ColorSpace::LinearSrgb.convert(self.apply(working_space.convert(pixel, ColorSpace::LinearSrgb)), output_space);
+            Vec3::new(color.x().clamp(0.0, 1.0), color.y().clamp(0.0, 1.0), color.z().clamp(0.0, 1.0))
+        };
+        Image {
+            width: image.width,
+            height: image.height,
+            pixels: image.pixels.iter().map(|&pixel| display(pixel)).collect(),
+        }
+    }
+}
+
+fn scale_luminance(color: Vec3, curve: impl Fn(Real) -> Real) -> Vec3 {
+    let l = luminance(color);
+    if l <= 0.0 {
+        return Vec3::new(0.0, 0.0, 0.0);
+    }
+    (curve(l) / l) * color
+}
+
+// sRGB to the ACES AP1 rendering space and back, with the RRT saturation folded in
+const ACES_INPUT: Matrix3 = [
+    [0.59719, 0.35458, 0.04823],
+    [0.07600, 0.90834, 0.01566],
+    [0.02840, 0.13383, 0.83777],
+];
+const ACES_OUTPUT: Matrix3 = [
+    [1.60475, -0.53108, -0.07367],
+    [-0.10208, 1.10813, -0.00605],
+    [-0.00327, -0.07276, 1.07602],
+];
+
+fn aces_filmic(color: Vec3) -> Vec3 {
+    let v = transform(&ACES_INPUT, color);
+    let fit = |v: Real| (v * (v + 0.0245786) - 0.000090537) / (v * (0.983729 * v + 0.4329510) + 0.238081);
+    transform(&ACES_OUTPUT, Vec3::new(fit(v.x()), fit(v.y()), fit(v.z())))
+}
+
+// AgX inset and outset matrices for sRGB primaries, and the log2 range the curve covers
+const AGX_INSET: Matrix3 = [
+    [0.842479062253094, 0.0784335999999992, 0.0792237451477643],
+    [0.0423282422610123, 0.878468636469772, 0.0791661274605434],
+    [0.0423756549057051, 0.0784336, 0.879142973793104],
+];
+const AGX_OUTSET: Matrix3 = [
+    [1.19687900512017, -0.0980208811401368, -0.0990297440797205],
+    [-0.0528968517574562, 1.15190312990417, -0.0989611768448433],
+    [-0.0529716355144438, -0.0980434501171241, 1.15107367264116],
+];
+const AGX_MIN_EV: Real = -12.47393;
+const AGX_MAX_EV: Real = 4.026069;
+
+fn agx(color: Vec3) -> Vec3 {
+    let v = transform(&AGX_INSET, color);
+    let curve = |c: Real| {
+        let x = (c.max(1E-10).log2().clamp(AGX_MIN_EV, AGX_MAX_EV) - AGX_MIN_EV) / (AGX_MAX_EV - AGX_MIN_EV);
+        let x2 = x * x;
+        let x4 = x2 * x2;
+        15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x - 0.00232
+    };
+    let v = transform(&AGX_OUTSET, Vec3::new(curve(v.x()), curve(v.y()), curve(v.z())));
+    // the curve produces display encoded values, taken back to linear so write_color can encode them
+    let linear = |c: Real| c.max(0.0).powf(2.2);
+    Vec3::new(linear(v.x()), linear(v.y()), linear(v.z()))
+}
+
+// Chromaticity of a light at the given temperature: the CIE daylight locus from 4000K up,
+// the Planckian locus (Kim et al. 2002) below that.
+fn temperature_to_xy(temperature: Real) -> (Real, Real) {
+    let t = temperature.clamp(1667.0, 25000.0);
+    if t >= 4000.0 {
+        let x = if t <= 7000.0 {
+            -4.6070E9 / (t * t * t) + 2.9678E6 / (t * t) + 0.09911E3 / t + 0.244063
+        } else {
+            -2.0064E9 / (t * t * t) + 1.9018E6 / (t * t) + 0.24748E3 / t + 0.237040
+        };
+        (x, -3.0 * x * x + 2.87 * x - 0.275)
+    } else {
+        let x = -0.2661239E9 / (t * t * t) - 0.2343589E6 / (t * t) + 0.8776956E3 / t + 0.179910;
+        let y = if t <= 2222.0 {
+            -1.1063814 * x * x * x - 1.34811020 * x * x + 2.18555832 * x - 0.20219683
+        } else {
+            -0.9549476 * x * x * x - 1.37418593 * x * x + 2.09137015 * x - 0.16748867
+        };
+        (x, y)
+    }
+}
+
+const BRADFORD: Matrix3 = [
+    [0.8951, 0.2664, -0.1614],
+    [-0.7502, 1.7135, 0.0367],
+    [0.0389, -0.0685, 1.0296],
+];
+const BRADFORD_INVERSE: Matrix3 = [
+    [0.9869929, -0.1470543, 0.1599627],
+    [0.4323053, 0.5183603, 0.0492912],
+    [-0.0085287, 0.0400428, 0.9684867],
+];
+
+// Bradford chromatic adaptation from a light of the given temperature to the D65 white of sRGB.
+fn white_balance(color: Vec3, temperature: Real) -> Vec3 {
+    let (x, y) = temperature_to_xy(temperature);
+    let source = transform(&BRADFORD, Vec3::new(x / y, 1.0, (1.0 - x - y) / y));
+    let target = transform(&BRADFORD, Vec3::new(0.95047, 1.0, 1.08883));
+    let cone = transform(&BRADFORD, rgb_to_xyz(color));
+    let adapted = Vec3::new(
+        cone.x() * target.x() / source.x(),
+        cone.y() * target.y() / source.y(),
+        cone.z() * target.z() / source.z(),
+    );
+    xyz_to_rgb(transform(&BRADFORD_INVERSE, adapted))
+}
@@ -1,29 +1,30 @@
 use core::panic;
 use std::ops::{Add, Sub, Div, Mul, Neg, Index, IndexMut, AddAssign, MulAssign, DivAssign};
 use rand::{random, Rng};
+use crate::rtweekend::Real;
 
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub struct Vec3 {
-    x: f64,
-    y: f64,
-    z: f64,
+    x: Real,
+    y: Real,
+    z: Real,
 }
 
 impl Vec3{
 
-    pub fn new(x: f64, y: f64, z: f64) -> Self {
-        Vec3 { x: x, y: y, z: z }
+    pub fn new(x: Real, y: Real, z: Real) -> Self {
+        Vec3 { x, y, z }
     }
 
     pub fn random() -> Self {
         Self {
-            x: random::<f64>(),
-            y: random::<f64>(),
-            z: random::<f64>(),
+            x: random::<Real>(),
+            y: random::<Real>(),
+            z: random::<Real>(),
         }
     }
 
-    pub fn random_minmax(min: f64, max: f64) -> Self {
+    pub fn random_minmax(min: Real, max: Real) -> Self {
         Self {
             x: rand::thread_rng().gen_range(min..max),
             y: rand::thread_rng().gen_range(min..max),
@@ -36,26 +37,31 @@ impl Vec3{
         (self.x.abs() < s) && (self.y.abs() < s) && (self.z.abs() < s)
     }
 
-    pub fn x(&self) -> f64 {
+    pub fn x(&self) -> Real {
         self.x
     }
 
-    pub fn y(&self) -> f64 {
+    pub fn y(&self) -> Real {
         self.y
     }
 
-    pub fn z(&self) -> f64 {
+    pub fn z(&self) -> Real {
         self.z
     }  
 
-    pub fn length_squared(&self) -> f64 {
+    pub fn length_squared(&self) -> Real {
         self.x * self.x + self.y * self.y + self.z * self.z
     }
 
-    pub fn length(&self) -> f64 {
+    pub fn length(&self) -> Real {
         self.length_squared().sqrt()
     }
 
+    // componentwise absolute value
+    pub fn abs(&self) -> Vec3 {
+        Vec3 { x: self.x.abs(), y: self.y.abs(), z: self.z.abs() }
+    }
+
 }
 
 // overloading for the addition operator +
@@ -87,7 +93,7 @@ impl Sub for Vec3 {
     }
 }
 
-// overloading for the multiplication operation for Vec3 * f64
+// overloading for the multiplication operation for Vec3 * Real
 impl Mul for Vec3 {
     type Output = Self;
     fn mul(self, rhs: Self) -> Self::Output {
@@ -99,8 +105,8 @@ impl Mul for Vec3 {
     }
 }
 
-// overloading the multiplication operation for f64 * Vec3
-impl Mul<Vec3> for f64 {
+// overloading the multiplication operation for Real * Vec3
+impl Mul<Vec3> for Real {
     type Output = Vec3;
 
     fn mul(self, rhs: Vec3) -> Self::Output {
@@ -114,9 +120,9 @@ impl Mul<Vec3> for f64 {
 
 
 // overloading for the mumtiplication by float
-impl Mul<f64> for Vec3 {
+impl Mul<Real> for Vec3 {
     type Output = Self;
-    fn mul(self, rhs: f64) -> Self::Output {
+    fn mul(self, rhs: Real) -> Self::Output {
         Vec3 {
             x: self.x * rhs,
             y: self.y * rhs,
@@ -126,9 +132,9 @@ impl Mul<f64> for Vec3 {
 }
 
 //overloading for the divide operator /
-impl Div<f64> for Vec3 {
+impl Div<Real> for Vec3 {
     type Output = Self;
-    fn div(self, t:f64) -> Self::Output {
+    fn div(self, t:Real) -> Self::Output {
         Vec3 {
             x: self.x / t,
             y: self.y / t,
@@ -139,8 +145,8 @@ impl Div<f64> for Vec3 {
 
 // overloading for the [] operation
 impl Index<usize> for Vec3 {
-    type Output = f64;
-    fn index(&self, i: usize) -> &f64 {
+    type Output = Real;
+    fn index(&self, i: usize) -> &Real {
         match i {
             0 => &self.x,
             1 => &self.y,
@@ -152,7 +158,7 @@ impl Index<usize> for Vec3 {
 
 // overloading for the [] operation in the mutable context 
 impl IndexMut<usize> for Vec3 {
-    fn index_mut(&mut self, i: usize) -> &mut f64 {
+    fn index_mut(&mut self, i: usize) -> &mut Real {
         match i {
             0 => &mut self.x,
             1 => &mut self.y,
@@ -172,18 +178,18 @@ impl AddAssign for Vec3 {
 }
 
 // Overloading for the *= operation
-impl MulAssign<f64> for Vec3 {
+impl MulAssign<Real> for Vec3 {
 
-    fn mul_assign(&mut self, t: f64) {
+    fn mul_assign(&mut self, t: Real) {
         self.x = self.x * t;
         self.y = self.y * t;
         self.z = self.z * t;
     }
 }
 
-impl DivAssign<f64> for Vec3 {
+impl DivAssign<Real> for Vec3 {
 
-    fn div_assign(&mut self, t: f64) {
+    fn div_assign(&mut self, t: Real) {
         self.x = self.x / t;
         self.y = self.y / t;
         self.z = self.z / t;
@@ -191,7 +197,7 @@ impl DivAssign<f64> for Vec3 {
 }
 
 
-pub fn dot(u: Vec3, v: Vec3) -> f64 {
+pub fn dot(u: Vec3, v: Vec3) -> Real {
     u.x * v.x + u.y * v.y + u.z * v.z
 }
 
@@ -204,8 +210,7 @@ pub fn cross(u: Vec3, v: Vec3) -> Vec3 {
 }
 
 pub fn unit_vector(v: Vec3) -> Vec3 {
-    let result = v / v.length();
-    result
+    v / v.length()
 }
 
 pub fn random_in_unit_sphere() -> Vec3 {
@@ -233,7 +238,7 @@ pub fn reflect(v: Vec3, n: Vec3) -> Vec3 {
     v - 2.0 * dot(v, n) * n
 }
 
-pub fn refract(uv: Vec3, n: Vec3, etai_over_etat: f64) -> Vec3 {
+pub fn refract(uv: Vec3, n: Vec3, etai_over_etat: Real) -> Vec3 {
     let cos_theta = dot(-uv, n).min(1.0);
     let r_out_perp = etai_over_etat * (uv + cos_theta * n);
     let r_out_parallel = -(1.0 - r_out_perp.length_squared()).abs().sqrt() * n;
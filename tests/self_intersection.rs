@@ -0,0 +1,122 @@
+// Rays spawned from a hit must not find the surface they leave again. The margins are tightest in
+// single precision, so run this with --features f32 as well as without.
+use std::rc::Rc;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use raytracing::hittable::{HitRecord, Hittable};
+use raytracing::interval::Interval;
+use raytracing::material::Lambertian;
+use raytracing::ray::Ray;
+use raytracing::rtweekend::{Real, INFINITY};
+use raytracing::sphere::Sphere;
+use raytracing::sphere_group::SphereGroup;
+use raytracing::vec3::{dot, unit_vector, Vec3};
+
+const RAYS: usize = 50_000;
+
+fn random_unit(rng: &mut StdRng) -> Vec3 {
+    loop {
+        let v = Vec3::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0));
+        if v.length_squared() > 1e-6 && v.length_squared() <= 1.0 {
+            return unit_vector(v);
+        }
+    }
+}
+
+// Spawns RAYS rays from hits on the sphere at center with the given radius, half of them between
+// 0.1 and 1e-8 radians off its tangent plane, and counts those that come back to it. A ray leaving
+// the convex side can't meet the sphere again; one entering it must reach the far side, 2 |radius|
+// cos(theta) away.
+fn self_hits(world: &dyn Hittable, id: u32, center: Vec3, radius: Real, seed: u64) -> usize {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let size = radius.abs();
+    let mut failures = 0;
+    let mut spawned = 0;
+    while spawned < RAYS {
+        // the ground is only ever seen near its top, the small spheres from all around
+        let around = if size > 100.0 {
+            unit_vector(Vec3::new(rng.gen_range(-0.01..0.01), 1.0, rng.gen_range(-0.01..0.01)))
+        } else {
+            random_unit(&mut rng)
+        };
+        let target = center + size * around;
+        let origin = if rng.gen_bool(0.5) {
+            center + rng.gen_range(0.0..0.9) * size * random_unit(&mut rng)
+        } else {
+            let away = random_unit(&mut rng);
+            let away = if dot(away, around) < 0.0 { -away } else { away };
+            target + rng.gen_range(0.1..10.0) * away
+        };
+        let mut rec = HitRecord::initialize();
+        if !world.hit(&Ray::new(origin, target - origin), Interval::new(0.0, INFINITY), &mut rec) || rec.object_id != id {
+            continue;
+        }
+
+        let outward = unit_vector(rec.p - center);
+        // scattered directions aren't normalized
+        let length = rng.gen_range(1e-3..2.0);
+        let direction = length * if rng.gen_bool(0.5) {
+            random_unit(&mut rng)
+        } else {
+            let random = random_unit(&mut rng);
+            let tangent = unit_vector(random - dot(random, outward) * outward);
+            let angle = rng.gen_range(1.0..8.0);
+            let sign = if rng.gen_bool(0.5) { 1.0 } else { -1.0 };
+            unit_vector(tangent + sign * (10.0 as Real).powf(-angle) * outward)
+        };
+        spawned += 1;
+
+        let mut again = HitRecord::initialize();
+        let hit = world.hit(&rec.spawn_ray(direction), Interval::new(0.0, INFINITY), &mut again) && again.object_id == id;
+        // spawn_ray picks the side from the geometric normal, which for directions all but in the
+        // tangent plane can disagree with outward
+        let side = if dot(direction, rec.normal) < 0.0 { -rec.normal } else { rec.normal };
+        let cos_theta = dot(direction / length, outward).abs();
+        if dot(side, outward) > 0.0 {
+            failures += hit as usize;
+        } else if cos_theta > 1e-3 && (!hit || again.t * length < size * cos_theta) {
+            failures += 1;
+        }
+    }
+    failures
+}
+
+fn check(world: &dyn Hittable, id: u32, center: Vec3, radius: Real, seed: u64) {
+    let failures = self_hits(world, id, center, radius, seed);
+    assert_eq!(failures, 0, "{} of {} rays spawned from the sphere of radius {} came back to it", failures, RAYS, radius);
+}
+
+fn material() -> Rc<Lambertian> {
+    Rc::new(Lambertian::new(Vec3::new(0.5, 0.5, 0.5)))
+}
+
+#[test]
+fn ground() {
+    let center = Vec3::new(0.0, -1000.0, 0.0);
+    check(&Sphere::new(center, 1000.0, material()), 0, center, 1000.0, 1);
+}
+
+#[test]
+fn small_sphere() {
+    let center = Vec3::new(4.3, 0.2, -2.7);
+    check(&Sphere::new(center, 0.2, material()), 0, center, 0.2, 2);
+}
+
+#[test]
+fn hollow_sphere() {
+    let center = Vec3::new(0.0, 1.0, 0.0);
+    check(&Sphere::new(center, -0.9, material()), 0, center, -0.9, 3);
+}
+
+#[test]
+fn sphere_group() {
+    let mut group = SphereGroup::new();
+    let centers = [Vec3::new(4.3, 0.2, -2.7), Vec3::new(-3.1, 0.2, 1.9), Vec3::new(0.7, 0.2, 5.2), Vec3::new(-6.4, 0.2, -0.8), Vec3::new(2.2, 0.2, 2.2)];
+    for center in centers {
+        group.add(Sphere::new(center, 0.2, material()));
+    }
+    for (id, center) in centers.into_iter().enumerate() {
+        check(&group, id as u32, center, 0.2, 4 + id as u64);
+    }
+}